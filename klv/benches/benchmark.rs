@@ -1,5 +1,5 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use klv::{from_bytes, uasdls::UASDatalinkLS};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use klv::{frame, from_bytes, peek_header, to_bytes, uasdls::UASDatalinkLS, KLVReader};
 
 const KLV_FRAME_DATA: &[u8] = &[
     0x06, 0x0e, 0x2b, 0x34, 0x02, 0x0b, 0x01, 0x01, 0x0e, 0x01, 0x03, 0x01, 0x01, 0x00, 0x00, 0x00,
@@ -24,5 +24,48 @@ fn bench_main(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_main);
+fn bench_reader(c: &mut Criterion) {
+    c.bench_function("klv_reader_iterate_sample", |b| {
+        b.iter(|| {
+            let n = KLVReader::from_bytes(black_box(&KLV_FRAME_DATA[18..]))
+                .map_while(|x| x.ok())
+                .count();
+            assert_eq!(n, 26);
+        })
+    });
+}
+
+fn bench_peek_header(c: &mut Criterion) {
+    // Short form, 1Byte, 2Byte, 4ByteのLong form
+    let content = vec![0u8; 70000];
+    let key = KLV_FRAME_DATA[..16].try_into().unwrap();
+    for len in [100, 200, 1000, 70000] {
+        let buf = frame(key, &content[..len]);
+        c.bench_function(&format!("klv_peek_header_{}", len), |b| {
+            b.iter(|| {
+                let (_, content_len, _) = peek_header(black_box(&buf)).unwrap();
+                assert_eq!(content_len, len);
+            })
+        });
+    }
+}
+
+fn bench_roundtrip(c: &mut Criterion) {
+    let x = from_bytes::<UASDatalinkLS>(KLV_FRAME_DATA).unwrap();
+    c.bench_function("klv_roundtrip_UASDLS_sample", |b| {
+        b.iter(|| {
+            let buf = to_bytes(black_box(&x)).unwrap();
+            let y = from_bytes::<UASDatalinkLS>(&buf).unwrap();
+            assert_eq!(x, y);
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_main,
+    bench_reader,
+    bench_peek_header,
+    bench_roundtrip
+);
 criterion_main!(benches);
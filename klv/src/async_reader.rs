@@ -0,0 +1,129 @@
+//! Read KLV items from tokio AsyncRead
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{parse_length, LengthOctet};
+
+/// contentのitemを1つずつ非同期に読み出す
+///
+/// 全体をバッファせずにKey, Length, Valueの順に必要な分だけ読む
+pub struct AsyncKLVReader<R> {
+    reader: R,
+}
+
+impl<R: AsyncRead + Unpin> AsyncKLVReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    /// 次のitemを読み出す
+    ///
+    /// itemの境界でEOFになった場合はNone、item途中のEOFはエラーを返す
+    pub async fn next(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let mut key = [0; 1];
+        if self.reader.read(&mut key).await? == 0 {
+            return Ok(None);
+        }
+        let mut length = [0; 9];
+        self.reader.read_exact(&mut length[..1]).await?;
+        // Long formであれば続くLengthを読み足す
+        let length_len = match LengthOctet::from_u8(length[0]) {
+            LengthOctet::Long(x) if x as usize >= length.len() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported length [{}]", x),
+                ))
+            }
+            LengthOctet::Long(x) => 1 + x as usize,
+            _ => 1,
+        };
+        self.reader.read_exact(&mut length[1..length_len]).await?;
+        let (_, content_len) = parse_length(&length[..length_len])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // Lengthは信頼できないので先に確保せず、届いた分だけ読む
+        let mut value = vec![];
+        (&mut self.reader)
+            .take(content_len as u64)
+            .read_to_end(&mut value)
+            .await?;
+        if value.len() < content_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("item is short, expect {} got {}", content_len, value.len()),
+            ));
+        }
+        Ok(Some((key[0], value)))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use tokio::io::AsyncWriteExt;
+
+    use crate::async_reader::AsyncKLVReader;
+
+    #[tokio::test]
+    async fn test_async_reader() {
+        let (mut tx, rx) = tokio::io::duplex(4);
+        let mut content = vec![10, 1, 0x7f, 11, 0x81, 200];
+        content.extend_from_slice(&[0xaa; 200]);
+        content.extend_from_slice(&[12, 0]);
+        // 冗長なLong form
+        content.extend_from_slice(&[13, 0x82, 0x00, 0x05, 1, 2, 3, 4, 5]);
+        let writer = tokio::spawn(async move {
+            for x in content.chunks(3) {
+                tx.write_all(x).await.unwrap();
+            }
+        });
+
+        let mut reader = AsyncKLVReader::new(rx);
+        assert_eq!(reader.next().await.unwrap(), Some((10, vec![0x7f])));
+        assert_eq!(reader.next().await.unwrap(), Some((11, vec![0xaa; 200])));
+        assert_eq!(reader.next().await.unwrap(), Some((12, vec![])));
+        assert_eq!(
+            reader.next().await.unwrap(),
+            Some((13, vec![1, 2, 3, 4, 5]))
+        );
+        assert_eq!(reader.next().await.unwrap(), None);
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_async_reader_eof_in_item() {
+        let (mut tx, rx) = tokio::io::duplex(64);
+        tx.write_all(&[10, 1, 0x7f, 11, 4, 0x01]).await.unwrap();
+        drop(tx);
+
+        let mut reader = AsyncKLVReader::new(rx);
+        assert_eq!(reader.next().await.unwrap(), Some((10, vec![0x7f])));
+        let e = reader.next().await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn test_async_reader_huge_length() {
+        // 実際のデータよりはるかに大きなLength
+        for buf in [
+            &[10, 0x84, 0xff, 0xff, 0xff, 0xff, 0x01, 0x02][..],
+            &[
+                10, 0x88, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+            ][..],
+        ] {
+            let (mut tx, rx) = tokio::io::duplex(64);
+            tx.write_all(buf).await.unwrap();
+            drop(tx);
+
+            let mut reader = AsyncKLVReader::new(rx);
+            let e = reader.next().await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
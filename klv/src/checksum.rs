@@ -0,0 +1,122 @@
+//! MISB ST 0601のchecksum(BCC-16)
+
+use crate::error::{Error, Result};
+
+/// 値を0にしたchecksum(Key 1, Length 2)のitem
+pub const CHECKSUM_PLACEHOLDER: [u8; 4] = [0x01, 0x02, 0x00, 0x00];
+
+/// 16bitのBlock Character Check
+///
+/// 先頭から偶数番目のbyteを上位、奇数番目のbyteを下位に足し込む。
+/// 位置は `update` に渡したbyte数の合計で数えるので、分割して渡しても一括で計算した場合と同じになる
+///
+/// ```
+/// use klv::Bcc16;
+///
+/// let mut bcc = Bcc16::new();
+/// bcc.update(&[0x01, 0x02]).update(&[0x03]);
+/// assert_eq!(bcc.finalize(), klv::bcc16(&[0x01, 0x02, 0x03]));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bcc16 {
+    sum: u16,
+    len: usize,
+}
+
+impl Bcc16 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, buf: &[u8]) -> &mut Self {
+        for x in buf {
+            let shift = 8 * ((self.len + 1) % 2);
+            self.sum = self.sum.wrapping_add((*x as u16) << shift);
+            self.len += 1;
+        }
+        self
+    }
+
+    pub fn finalize(&self) -> u16 {
+        self.sum
+    }
+}
+
+/// bufのBCC-16を一括で計算する
+pub fn bcc16(buf: &[u8]) -> u16 {
+    Bcc16::new().update(buf).finalize()
+}
+
+/// contentの末尾にchecksumのplaceholderを書き込み、その位置を返す
+///
+/// 他のitemを書き込んで `frame` した後に `finalize_checksum` で値を埋める。
+/// 位置はcontentの先頭からなので、`finalize_checksum` にはヘッダの長さを加えて渡す
+pub fn reserve_checksum(content: &mut Vec<u8>) -> usize {
+    let offset = content.len();
+    content.extend_from_slice(&CHECKSUM_PLACEHOLDER);
+    offset
+}
+
+/// `placeholder_offset` にあるchecksumのitemの値をBCC-16で書き換える
+///
+/// 先頭からchecksumのLengthまでを対象に計算し、書き込んだ値を返す
+pub fn finalize_checksum(buf: &mut [u8], placeholder_offset: usize) -> Result<u16> {
+    let value_at = placeholder_offset + 2;
+    if buf.len() < value_at + 2 {
+        return Err(Error::ContentLenght);
+    }
+    if buf[placeholder_offset..value_at] != CHECKSUM_PLACEHOLDER[..2] {
+        return Err(Error::Key(format!(
+            "checksum item is not found at {}, got {:02x?}",
+            placeholder_offset,
+            &buf[placeholder_offset..value_at]
+        )));
+    }
+    let checksum = bcc16(&buf[..value_at]);
+    buf[value_at..value_at + 2].copy_from_slice(&checksum.to_be_bytes());
+    Ok(checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bcc16, finalize_checksum, reserve_checksum, Bcc16};
+    use crate::{checksum_range, frame, KLVReader};
+
+    #[test]
+    fn test_bcc16() {
+        assert_eq!(bcc16(&[]), 0);
+        assert_eq!(bcc16(&[0x01, 0x02, 0x03]), 0x0402);
+        assert_eq!(bcc16(&[0xff, 0xff, 0x01, 0x01]), 0x0100);
+
+        let buf = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        for n in [1, 2, 3, 7, 64] {
+            let mut bcc = Bcc16::new();
+            for chunk in buf.chunks(n) {
+                bcc.update(chunk);
+            }
+            assert_eq!(bcc.finalize(), bcc16(&buf), "chunk {}", n);
+        }
+    }
+
+    #[test]
+    fn test_finalize_checksum() {
+        // checksumを先に書き込み、後から値を埋める
+        let mut content = vec![];
+        let at = reserve_checksum(&mut content);
+        content.extend_from_slice(&[10, 2, 0x12, 0x34, 11, 1, 0x56]);
+        let mut buf = frame(b"TESTDATA00000000", &content);
+        let at = buf.len() - content.len() + at;
+        let checksum = finalize_checksum(&mut buf, at).unwrap();
+
+        let range = checksum_range(&buf, 1).unwrap();
+        assert_eq!(range, 0..at + 2);
+        assert_eq!(bcc16(&buf[range]), checksum);
+        let item = KLVReader::from_bytes(&buf[17..]).next().unwrap().unwrap();
+        assert_eq!(item.key_and_content(), (1, &checksum.to_be_bytes()[..]));
+
+        // placeholderではない位置
+        assert!(finalize_checksum(&mut buf, at + 4).is_err());
+        let len = buf.len();
+        assert!(finalize_checksum(&mut buf, len - 2).is_err());
+    }
+}
@@ -1,9 +1,14 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Range, RangeBounds};
+use std::sync::mpsc;
+use std::thread;
+
 use byteorder::{BigEndian, ByteOrder};
-use serde::de::{self, DeserializeSeed, MapAccess, Visitor};
-use serde::Deserialize;
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
-use crate::parse_length;
+use crate::{ber_length, parse_length};
 
 pub struct Deserializer<'de> {
     input: &'de [u8],
@@ -32,9 +37,20 @@ where
     }
 }
 
-impl<'de> Deserializer<'de> {}
+impl<'de> Deserializer<'de> {
+    /// Lengthとvalueを読み出す。Lengthが残りのbyte数を超える場合はエラー
+    fn read_value(&mut self) -> Result<&'de [u8]> {
+        let (length_len, content_len) = ber_length(&self.input[self.position..])?;
+        let pos = self.position + length_len;
+        if self.input.len() < pos + content_len {
+            return Err(Error::ContentLenght);
+        }
+        self.position = pos + content_len;
+        Ok(&self.input[pos..pos + content_len])
+    }
+}
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
     // 不明な型をParseする場合
@@ -226,12 +242,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        let (length_len, content_len) =
-            parse_length(&self.input[self.position..]).map_err(Error::UnsupportedLength)?;
-        let pos = self.position + length_len;
-        self.position += length_len + content_len;
-        let s = std::str::from_utf8(&self.input[pos..pos + content_len])
-            .map_err(|_e| Error::ExpectedString)?;
+        let s = std::str::from_utf8(self.read_value()?).map_err(|_e| Error::ExpectedString)?;
         visitor.visit_borrowed_str(s)
     }
 
@@ -246,30 +257,25 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        let (length_len, content_len) =
-            parse_length(&self.input[self.position..]).map_err(Error::UnsupportedLength)?;
-        let pos = self.position + length_len;
-        self.position += length_len + content_len;
-        visitor.visit_borrowed_bytes(&self.input[pos..pos + content_len])
+        visitor.visit_borrowed_bytes(self.read_value()?)
     }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let (length_len, content_len) =
-            parse_length(&self.input[self.position..]).map_err(Error::UnsupportedLength)?;
-        let pos = self.position + length_len;
-        self.position += length_len + content_len;
-        visitor.visit_byte_buf(Vec::from(&self.input[pos..pos + content_len]))
+        visitor.visit_byte_buf(Vec::from(self.read_value()?))
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        if self.input[self.position] == 0 {
-            self.position += 1;
+        // NoneはKeyごと省略して書き込むが、以前のエンコーダは長さ0で書き込んでいたため長さ0もNoneとする。
+        // 空の文字列やbyte列をSomeとして読む場合は `serde_helpers::option_str` などを使う
+        let (length_len, content_len) = ber_length(&self.input[self.position..])?;
+        if content_len == 0 {
+            self.position += length_len;
             visitor.visit_none()
         } else {
             visitor.visit_some(self)
@@ -306,24 +312,37 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         unimplemented!()
     }
 
-    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        // Tuple全体のLengthの中に、Lengthを持つ各要素が並ぶ
+        let (length_len, content_len) = ber_length(&self.input[self.position..])?;
+        let end = self.position + length_len + content_len;
+        if self.input.len() < end {
+            return Err(Error::ContentLenght);
+        }
+        self.position += length_len;
+        let value = visitor.visit_seq(KLVTupleAccess::new(&mut *self, len))?;
+        if self.position != end {
+            return Err(Error::TypeLength(format!(
+                "tuple length {} does not match its elements",
+                content_len
+            )));
+        }
+        Ok(value)
     }
 
-    // Tuple structs look just like sequences in JSON.
     fn deserialize_tuple_struct<V>(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        self.deserialize_tuple(len, visitor)
     }
 
     fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value>
@@ -351,7 +370,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     {
         let len = self.input[self.position];
         let v = BigEndian::read_u32(&self.input[self.position + 1..]);
-        let c = std::char::from_u32(v as u32);
+        let c = std::char::from_u32(v);
         if let Some(x) = c {
             self.position += 1 + len as usize;
             visitor.visit_char(x)
@@ -375,11 +394,20 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     {
         // jsonの場合はtoplevelがMapなのでmapに飛ばしている
         // UniversalKeyとContentLengthを取り出してDeseliarizerに処理を移乗する
-        // top levelstructと内蔵のstructで扱いを分ける?
+        if self.position != 0 {
+            // 親のitemのvalueとして埋め込まれたセット。Universal Keyは持たず、Lengthの後にitemが続く。
+            // 同じinputを読み進めるので、&strなどは元のbufを借用したままになる
+            let (length_len, content_len) = ber_length(&self.input[self.position..])?;
+            self.position += length_len;
+            let end = self.position + content_len;
+            if self.input.len() < end {
+                return Err(Error::ContentLenght);
+            }
+            return visitor.visit_map(KLVVisitor::new(self, end));
+        }
         let key = &self.input[self.position..self.position + 16];
         // BERに従うとする
-        let (length_len, content_len) =
-            parse_length(&self.input[self.position + 16..]).map_err(Error::UnsupportedLength)?;
+        let (length_len, content_len) = ber_length(&self.input[self.position + 16..])?;
         if name.as_bytes() != key {
             return Err(Error::Key(format!(
                 "Universal key is unmatched get {:02x?}, expect {:02x?}",
@@ -407,8 +435,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         // デシリアライズ先がない場合はデータを無視する
-        let (length_len, content_len) =
-            parse_length(&self.input[self.position..]).map_err(Error::UnsupportedLength)?;
+        let (length_len, content_len) = ber_length(&self.input[self.position..])?;
         self.position += length_len + content_len;
         visitor.visit_unit()
     }
@@ -450,16 +477,82 @@ impl<'de, 'a> MapAccess<'de> for KLVVisitor<'a, 'de> {
             return Err(Error::ExpectedMapEnd);
         }
         // Deserialize a map value.
-        seed.deserialize(&mut *self.de)
+        let value = seed.deserialize(&mut *self.de)?;
+        // valueがセットの終わりを超えて後続のデータを読んでいないか
+        if self.de.position > self.len {
+            return Err(Error::ContentLenght);
+        }
+        Ok(value)
+    }
+}
+
+struct KLVTupleAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+}
+
+impl<'a, 'de> KLVTupleAccess<'a, 'de> {
+    fn new(de: &'a mut Deserializer<'de>, len: usize) -> Self {
+        Self { de, len }
+    }
+}
+
+impl<'de, 'a> SeqAccess<'de> for KLVTupleAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+/// Universal KeyとLengthだけを読む
+///
+/// (Universal Key, contentの長さ, Universal KeyとLengthを合わせたヘッダの長さ)を返す。
+/// contentがバッファに揃っているかは確認しない
+pub fn peek_header(buf: &[u8]) -> Result<([u8; 16], usize, usize)> {
+    if buf.len() <= 16 {
+        return Err(Error::ContentLenght);
+    }
+    let (length_len, content_len) = ber_length(&buf[16..])?;
+    let key = buf[..16].try_into().unwrap();
+    Ok((key, content_len, 16 + length_len))
+}
+
+/// 1つのKLVセットだけを含むバッファであることを確認してパースする
+///
+/// Universal KeyとLengthから求めた長さがバッファ長と一致しない場合はエラーを返す
+pub fn validate_single_set(buf: &[u8]) -> Result<KLVMap<'_>> {
+    if buf.len() <= 16 {
+        return Err(Error::ContentLenght);
+    }
+    let (length_len, content_len) = ber_length(&buf[16..])?;
+    let framed_len = 16 + length_len + content_len;
+    match buf.len().cmp(&framed_len) {
+        std::cmp::Ordering::Less => Err(Error::ContentLenght),
+        std::cmp::Ordering::Greater => Err(Error::TrailingBytes(buf.len() - framed_len)),
+        std::cmp::Ordering::Equal => KLVMap::try_from_bytes(buf),
     }
 }
 
 /// Parse for unknown KLVdata
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KLVMap<'m> {
+    buf: &'m [u8],
     universal_key: &'m [u8],
     content_len: usize,
     values: Vec<KLVRaw<'m>>,
+    length_corrected: bool,
 }
 
 impl<'m> KLVMap<'m> {
@@ -469,49 +562,509 @@ impl<'m> KLVMap<'m> {
             return Err(Error::ContentLenght);
         }
         let universal_key = &buf[0..16];
-        let (length_len, content_len) =
-            parse_length(&buf[16..]).map_err(Error::UnsupportedLength)?;
-        let mut position = 16 + length_len;
-        let mut values = vec![];
-        while position < buf_len {
-            let (length_len, content_len) =
-                parse_length(&buf[position + 1..]).map_err(Error::UnsupportedLength)?;
-            values.push(KLVRaw::from(
-                buf[position],
-                position + length_len,
-                content_len,
-                &buf[position + length_len..],
-            ));
-            position += 1 + length_len + content_len;
+        let (length_len, content_len) = ber_length(&buf[16..])?;
+        let position = 16 + length_len;
+        if buf_len < position + content_len {
+            return Err(Error::ContentLenght);
         }
+        let values = KLVReader::from_bytes(&buf[position..position + content_len])
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(Self {
+            buf: &buf[..position + content_len],
             universal_key,
             content_len,
             values,
+            length_corrected: false,
         })
     }
 
+    /// contentのLengthがbufを超える場合にbufの終わりまでを読む
+    ///
+    /// Lengthを誤って書き込む実装向け。読めるitemだけを残し、不正なitemに到達したらそこまでとする。
+    /// Lengthを補正した場合は `length_corrected` がtrueになる
+    pub fn try_from_bytes_lenient(buf: &'m [u8]) -> Result<Self> {
+        if buf.len() <= 16 {
+            return Err(Error::ContentLenght);
+        }
+        let universal_key = &buf[0..16];
+        let (length_len, declared_len) = ber_length(&buf[16..])?;
+        let position = 16 + length_len;
+        let content_len = declared_len.min(buf.len() - position);
+        let values = KLVReader::from_bytes(&buf[position..position + content_len])
+            .map_while(|x| x.ok())
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            buf: &buf[..position + content_len],
+            universal_key,
+            content_len,
+            values,
+            length_corrected: content_len != declared_len,
+        })
+    }
+
+    /// `try_from_bytes_lenient` でcontentのLengthを補正したか
+    pub fn length_corrected(&self) -> bool {
+        self.length_corrected
+    }
+
+    /// Universal Keyからcontentの終わりまでのbyte列
+    pub fn as_bytes(&self) -> &'m [u8] {
+        self.buf
+    }
+
     pub fn universal_key(&'m self) -> &'m [u8] {
         self.universal_key
     }
+
+    /// Version Numberの違いを無視してUniversal Keyを比べる
+    pub fn key_matches_ignoring_version(&self, key: &[u8; 16]) -> bool {
+        crate::key_matches_ignoring_version(self.universal_key, key)
+    }
     pub fn content_len(&'m self) -> usize {
         self.content_len
     }
-    pub fn iter(&'m self) -> std::slice::Iter<KLVRaw<'m>> {
+    pub fn iter(&'m self) -> std::slice::Iter<'m, KLVRaw<'m>> {
         self.values.iter()
     }
 }
 
-#[derive(Debug)]
+/// contentの各itemの(Key, valueのoffset, valueの長さ)を最大N件までヒープを使わずに保持する
+///
+/// itemごとに `Vec` や `BTreeMap` を作りたくない用途向け。offsetはcontentの先頭から
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmallKlvIndex<const N: usize> {
+    entries: [(u8, usize, usize); N],
+    len: usize,
+}
+
+impl<const N: usize> SmallKlvIndex<N> {
+    /// Universal KeyとLengthを取り除いたcontentを受け取る
+    ///
+    /// itemがN件を超える場合や不正なitemがある場合はエラー
+    pub fn try_from_content(content: &[u8]) -> Result<Self> {
+        let mut index = Self {
+            entries: [(0, 0, 0); N],
+            len: 0,
+        };
+        for x in KLVReader::from_bytes(content) {
+            let x = x?;
+            if index.len == N {
+                return Err(Error::Message(format!("set has more than {} items", N)));
+            }
+            index.entries[index.len] = (x.key, x.position, x.length);
+            index.len += 1;
+        }
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 最初に現れたKeyの(valueのoffset, valueの長さ)
+    pub fn get(&self, key: u8) -> Option<(usize, usize)> {
+        self.iter()
+            .find(|x| x.0 == key)
+            .map(|(_, position, length)| (*position, *length))
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, (u8, usize, usize)> {
+        self.entries[..self.len].iter()
+    }
+}
+
+/// bufを借用せずにitemを保持するセット
+///
+/// serdeで他の形式に変換してプロセス間で受け渡す用途向け。valueはデコードせずにbyte列のまま持つ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OwnedSet {
+    pub key: [u8; 16],
+    pub items: Vec<(u8, Vec<u8>)>,
+}
+
+impl OwnedSet {
+    /// Universal Keyから始まるセットを読む
+    pub fn decode(framed: &[u8]) -> Result<Self> {
+        let map = KLVMap::try_from_bytes(framed)?;
+        let items = map
+            .iter()
+            .map(|x| {
+                let (key, value) = x.key_and_content();
+                (key, value.to_vec())
+            })
+            .collect();
+        Ok(Self {
+            key: framed[..16].try_into().unwrap(),
+            items,
+        })
+    }
+
+    /// Universal KeyとLengthを付けて書き込む。Lengthは最短の形式になる
+    pub fn encode(&self) -> Vec<u8> {
+        crate::frame_iter(&self.key, self.items.iter().map(|(k, v)| (*k, &v[..])))
+    }
+}
+
+/// contentにKeyが含まれるかをKeyとLengthだけを読んで調べる
+///
+/// 不正なitemに到達した場合はそれ以降を調べずにfalseとする
+pub fn content_contains_tag(content: &[u8], tag: u8) -> bool {
+    KLVReader::from_bytes(content)
+        .map_while(|x| x.ok())
+        .any(|x| x.key == tag)
+}
+
+/// contentに含まれるKeyの集合をKeyとLengthだけを読んで返す
+///
+/// 必須Keyとの差集合を取る用途向け。不正なitemに到達した場合はそれ以降を含めない
+pub fn tag_coverage(content: &[u8]) -> BTreeSet<u8> {
+    KLVReader::from_bytes(content)
+        .map_while(|x| x.ok())
+        .map(|x| x.key)
+        .collect()
+}
+
+/// 2つのcontentが `ignore` のKeyを除いて同じitemを持つかを比べる
+///
+/// itemの順序は問わない。どちらかに不正なitemがある場合はfalse
+pub fn content_eq_ignoring(a: &[u8], b: &[u8], ignore: &[u8]) -> bool {
+    let items = |content| -> Result<Vec<(u8, &[u8])>> {
+        let mut x = KLVReader::from_bytes(content)
+            .filter(|x| !matches!(x, Ok(x) if ignore.contains(&x.key)))
+            .map(|x| x.map(|x| x.key_and_content()))
+            .collect::<Result<Vec<_>>>()?;
+        x.sort();
+        Ok(x)
+    };
+    matches!((items(a), items(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// `ignore` のKeyを除いたitemの内容から、順序によらないハッシュ値を計算する
+///
+/// 実行ごとに変わらないようFNV-1a(64bit)を使う。`content_eq_ignoring` で等しいcontentは同じ値になる。
+/// 不正なitemに到達した場合はそれ以降を含めない
+pub fn content_hash(content: &[u8], ignore: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut items = KLVReader::from_bytes(content)
+        .map_while(|x| x.ok())
+        .filter(|x| !ignore.contains(&x.key))
+        .map(|x| x.key_and_content())
+        .collect::<Vec<_>>();
+    items.sort();
+    let mut hash = OFFSET_BASIS;
+    for (key, value) in items {
+        // 区切りが曖昧にならないようvalueの長さも含める
+        let len = (value.len() as u64).to_be_bytes();
+        for b in [key].iter().chain(len.iter()).chain(value.iter()) {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
+
+/// 別threadでcontentのitemを読み、(Key, value)を順にchannelへ送る
+///
+/// 読む側と使う側のthreadを分けるpipeline向け。valueはコピーして送る。
+/// 不正なitemは `KLVReader` と同様にエラーを送った後、読み直せる位置から続ける。
+/// 全itemを送り終えるとchannelが閉じる
+pub fn spawn_decode(content: Vec<u8>) -> mpsc::Receiver<Result<(u8, Vec<u8>)>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for x in KLVReader::from_bytes(&content) {
+            let x = x.map(|x| {
+                let (key, value) = x.key_and_content();
+                (key, value.to_vec())
+            });
+            // 受け手が居なくなったら読むのをやめる
+            if tx.send(x).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// checksumの対象となる範囲を返す
+///
+/// ST 0601に従い、Universal Keyの先頭からchecksum itemのLengthの終わりまでを対象とする。
+/// checksum itemが見つからない場合はNone
+pub fn checksum_range(framed: &[u8], checksum_tag: u8) -> Option<Range<usize>> {
+    if framed.len() <= 16 {
+        return None;
+    }
+    let (length_len, content_len) = parse_length(&framed[16..]).ok()?;
+    let header_len = 16 + length_len;
+    let content = framed.get(header_len..header_len + content_len)?;
+    KLVReader::from_bytes(content)
+        .map_while(|x| x.ok())
+        .find(|x| x.key == checksum_tag)
+        .map(|x| 0..header_len + x.position)
+}
+
+/// item毎のLengthの表現方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LengthMode {
+    /// 1Byte固定
+    OneByte,
+    /// 2Byte固定 BigEndian
+    TwoByteBE,
+    /// BER encoding
+    #[default]
+    Ber,
+}
+
+impl LengthMode {
+    fn parse(&self, buf: &[u8]) -> Result<(usize, usize)> {
+        match self {
+            LengthMode::OneByte => match buf.first() {
+                Some(x) => Ok((1, *x as usize)),
+                None => Err(Error::UnsupportedLength(
+                    "length octet is empty".to_string(),
+                )),
+            },
+            LengthMode::TwoByteBE if buf.len() < 2 => Err(Error::UnsupportedLength(format!(
+                "length octets are short, expect 2 got {}",
+                buf.len()
+            ))),
+            LengthMode::TwoByteBE => Ok((2, BigEndian::read_u16(buf) as usize)),
+            LengthMode::Ber => ber_length(buf),
+        }
+    }
+}
+
+/// Iterate items in content of KLV set
+///
+/// 値はデコードせずにKeyとLengthだけを読んで進む
+#[derive(Debug, Clone)]
+pub struct KLVReader<'buf> {
+    buf: &'buf [u8],
+    current: usize,
+    // contentの終わり。これを超えるitemはセットの外にはみ出している
+    content_end: usize,
+    failed: bool,
+    length_mode: LengthMode,
+    max_item_len: Option<usize>,
+    resync_tags: Option<Vec<u8>>,
+}
+
+impl<'buf> KLVReader<'buf> {
+    /// Universal KeyとLengthを取り除いたcontentを受け取る
+    pub fn from_bytes(buf: &'buf [u8]) -> Self {
+        Self::with_length_mode(buf, LengthMode::Ber)
+    }
+
+    /// BER以外のLength表現を使うLocal Set向け
+    pub fn with_length_mode(buf: &'buf [u8], length_mode: LengthMode) -> Self {
+        Self {
+            buf,
+            current: 0,
+            content_end: buf.len(),
+            failed: false,
+            length_mode,
+            max_item_len: None,
+            resync_tags: None,
+        }
+    }
+
+    /// 不正なLengthのitemに到達しても、既知のKeyを手がかりに読み進める
+    ///
+    /// 不正なitemはエラーとして返した後、以降で既知のKeyで始まり範囲内に収まるitemを探して再開する。
+    /// 値の中に既知のKeyと同じbyteがあれば誤って再開するため、あくまで経験則である
+    pub fn resync(mut self, known_tags: &[u8]) -> Self {
+        self.resync_tags = Some(known_tags.to_vec());
+        self
+    }
+
+    /// contentの終わりを指定する
+    ///
+    /// bufに後続のデータが含まれる場合に使う。`end` を超えるitemはエラーを返して終了する
+    pub fn with_content_end(mut self, end: usize) -> Self {
+        self.content_end = end.min(self.buf.len());
+        self
+    }
+
+    /// 1itemの長さの上限を設定する
+    ///
+    /// 上限を超える長さを持つitemに到達したら `Error::ItemTooLong` を返して終了する
+    pub fn with_max_item_len(mut self, n: usize) -> Self {
+        self.max_item_len = Some(n);
+        self
+    }
+
+    /// まだ読み出していない部分を返す
+    ///
+    /// 読み切った後に空でなければ末尾に不完全なitemが残っている
+    pub fn remaining(&self) -> &'buf [u8] {
+        &self.buf[self.current.min(self.content_end)..self.content_end]
+    }
+
+    /// 各itemの(key, valueのoffset, valueの長さ)を返す
+    ///
+    /// offsetはcontentの先頭からの位置。不正なitemに到達したら終了する
+    pub fn spans(self) -> impl Iterator<Item = (u8, usize, usize)> + 'buf {
+        self.map_while(|x| x.ok())
+            .map(|x| (x.key, x.position, x.length))
+    }
+
+    /// 各itemのvalueを `name` で引いたKeyの名称と組にして返す
+    ///
+    /// 名称が分からないKeyはNone。不正なitemに到達したら終了する
+    pub fn labeled<F>(self, name: F) -> impl Iterator<Item = (Option<&'static str>, &'buf [u8])>
+    where
+        F: Fn(u8) -> Option<&'static str> + 'buf,
+    {
+        self.map_while(|x| x.ok()).map(move |x| {
+            let (key, value) = x.key_and_content();
+            (name(key), value)
+        })
+    }
+
+    /// Keyごとにvalueを出現順にまとめる
+    ///
+    /// 同じKeyが繰り返し現れる場合もすべて残す。不正なitemに到達したら終了する
+    pub fn group_by_tag(self) -> BTreeMap<u8, Vec<&'buf [u8]>> {
+        let mut map = BTreeMap::<u8, Vec<&'buf [u8]>>::new();
+        for (key, value) in self.map_while(|x| x.ok()).map(|x| x.key_and_content()) {
+            map.entry(key).or_default().push(value);
+        }
+        map
+    }
+
+    /// 2回以上現れるKeyを昇順に返す
+    ///
+    /// まだ読み出していないitemが対象で、readerは進めない。不正なitemに到達したらそこまでで判断する
+    pub fn find_duplicate_tags(&self) -> Vec<u8> {
+        let mut seen = BTreeSet::new();
+        let mut duplicated = BTreeSet::new();
+        for x in self.clone().map_while(|x| x.ok()) {
+            if !seen.insert(x.key) {
+                duplicated.insert(x.key);
+            }
+        }
+        duplicated.into_iter().collect()
+    }
+
+    /// 各itemをKeyの位置と一緒にコールバックへ渡す
+    ///
+    /// 位置はcontentの先頭からのoffsetで、エラー報告に使う
+    pub fn for_each_with_offset(mut self, mut f: impl FnMut(usize, u8, Result<KLVRaw<'buf>>)) {
+        loop {
+            let offset = self.current;
+            match self.next() {
+                Some(x) => f(offset, self.buf[offset], x),
+                None => break,
+            }
+        }
+    }
+
+    /// Keyが範囲内のitemだけを返す。パースエラーはそのまま返す
+    pub fn filter_tags(
+        self,
+        range: impl RangeBounds<u8> + 'buf,
+    ) -> impl Iterator<Item = Result<KLVRaw<'buf>>> + 'buf {
+        self.filter(move |x| match x {
+            Ok(x) => range.contains(&x.key),
+            Err(_) => true,
+        })
+    }
+}
+
+impl<'buf> KLVReader<'buf> {
+    /// atの位置のitemを読み、(key, valueの位置, valueの長さ)を返す
+    fn read_item(&self, at: usize) -> Result<(u8, usize, usize)> {
+        let key = self.buf[at];
+        let (length_len, content_len) = self
+            .length_mode
+            .parse(&self.buf[at + 1..self.content_end])?;
+        if let Some(max) = self.max_item_len.filter(|max| content_len > *max) {
+            return Err(Error::ItemTooLong {
+                tag: key,
+                len: content_len,
+                max,
+            });
+        }
+        let position = at + 1 + length_len;
+        if self.content_end < position + content_len {
+            return Err(Error::ContentLenght);
+        }
+        Ok((key, position, content_len))
+    }
+
+    /// 既知のKeyで始まり、範囲内に収まるitemを探す
+    fn find_resync_point(&self, from: usize) -> Option<usize> {
+        let tags = self.resync_tags.as_ref()?;
+        (from..self.content_end)
+            .find(|at| tags.contains(&self.buf[*at]) && self.read_item(*at).is_ok())
+    }
+}
+
+impl<'buf> Iterator for KLVReader<'buf> {
+    type Item = Result<KLVRaw<'buf>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.current >= self.content_end {
+            return None;
+        }
+        let (key, position, content_len) = match self.read_item(self.current) {
+            Ok(x) => x,
+            Err(e) => {
+                match self.find_resync_point(self.current + 1) {
+                    Some(at) => self.current = at,
+                    None => self.failed = true,
+                }
+                return Some(Err(e));
+            }
+        };
+        self.current = position + content_len;
+        Some(Ok(KLVRaw::from(
+            key,
+            position,
+            content_len,
+            &self.buf[position..self.content_end],
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct KLVRaw<'m> {
     pub key: u8,
+    /// offset of value from head of content
     pub position: usize,
     pub length: usize,
     pub value: Option<&'m [u8]>,
 }
 
 impl<'m> KLVRaw<'m> {
+    /// Keyと値のbyte列を一度に返す。長さ0の値は空のsliceになる
+    pub fn key_and_content(&self) -> (u8, &'m [u8]) {
+        (self.key, self.value.unwrap_or_default())
+    }
+
+    /// 値が入れ子のLocal Setである場合にコピーせずに読み出す
+    pub fn nested_reader(&self) -> KLVReader<'m> {
+        KLVReader::from_bytes(self.value.unwrap_or_default())
+    }
+
+    /// 1つのitem(Key, Length, value)をbufの先頭から読む
+    ///
+    /// Lengthに対してbufが短い場合は `Error::ContentLenght` を返す。続くbyteは無視する
+    pub fn try_from_bytes(buf: &'m [u8]) -> Result<Self> {
+        if buf.len() < 2 {
+            return Err(Error::ContentLenght);
+        }
+        KLVReader::from_bytes(buf)
+            .next()
+            .unwrap_or(Err(Error::ContentLenght))
+    }
+
     pub fn from(key: u8, position: usize, length: usize, value: &'m [u8]) -> Self {
         if length > 0 {
             Self {
@@ -530,3 +1083,671 @@ impl<'m> KLVRaw<'m> {
         }
     }
 }
+
+/// BER LengthとそのLength分のbyte列(Pack)の繰り返しを読む
+///
+/// ST 0903のVMTI Target SeriesやST 0601のWaypoint Listのように、
+/// 1Byte Keyを持たないVariable Length Packが並んだvalue向け。
+/// Packが途中で切れている場合はエラーを1度返して終わる
+#[derive(Debug, Clone)]
+pub struct PackReader<'buf> {
+    buf: &'buf [u8],
+    current: usize,
+}
+
+impl<'buf> PackReader<'buf> {
+    pub fn from_bytes(buf: &'buf [u8]) -> Self {
+        Self { buf, current: 0 }
+    }
+}
+
+impl<'buf> Iterator for PackReader<'buf> {
+    type Item = Result<&'buf [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current >= self.buf.len() {
+            return None;
+        }
+        let rest = &self.buf[self.current..];
+        let pack = ber_length(rest).and_then(|(length_len, pack_len)| {
+            let pack = rest
+                .get(length_len..length_len + pack_len)
+                .ok_or(Error::ContentLenght)?;
+            Ok((pack, length_len + pack_len))
+        });
+        match pack {
+            Ok((pack, len)) => {
+                self.current += len;
+                Some(Ok(pack))
+            }
+            Err(e) => {
+                self.current = self.buf.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::de::{
+        checksum_range, content_eq_ignoring, content_hash, from_bytes, peek_header,
+        validate_single_set, KLVMap, KLVRaw, KLVReader, LengthMode, OwnedSet, PackReader,
+        SmallKlvIndex,
+    };
+    use crate::error::Error;
+
+    #[rustfmt::skip]
+    const SINGLE_SET: &[u8] = &[
+        0x54, 0x45, 0x53, 0x54, 0x44, 0x41, 0x54, 0x41, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30,
+        7,
+        10, 1, 0x7f,
+        11, 2, 0x01, 0x02,
+    ];
+
+    #[test]
+    fn test_validate_single_set() {
+        let x = validate_single_set(SINGLE_SET).unwrap();
+        assert_eq!(x.universal_key(), b"TESTDATA00000000");
+        assert_eq!(x.content_len(), 7);
+
+        let short = &SINGLE_SET[..SINGLE_SET.len() - 1];
+        match validate_single_set(short) {
+            Err(Error::ContentLenght) => {}
+            _ => unreachable!(),
+        }
+
+        let mut long = SINGLE_SET.to_vec();
+        long.extend_from_slice(&[0, 0]);
+        match validate_single_set(&long) {
+            Err(Error::TrailingBytes(2)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_reader_spans() {
+        let content = &SINGLE_SET[17..];
+        let spans = KLVReader::from_bytes(content).spans().collect::<Vec<_>>();
+        assert_eq!(spans, vec![(10, 2, 1), (11, 5, 2)]);
+
+        #[rustfmt::skip]
+        let content = [
+            1, 0,
+            2, 3, 0x01, 0x02, 0x03,
+            3, 129, 2, 0xff, 0xfe,
+        ];
+        let spans = KLVReader::from_bytes(&content).spans().collect::<Vec<_>>();
+        assert_eq!(spans, vec![(1, 2, 0), (2, 4, 3), (3, 10, 2)]);
+
+        // 範囲外を指すitemでは止まる
+        let spans = KLVReader::from_bytes(&content[..11])
+            .spans()
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec![(1, 2, 0), (2, 4, 3)]);
+    }
+
+    #[test]
+    fn test_reader_length_mode() {
+        let mut content = vec![10, 0x01, 0x2c];
+        content.extend_from_slice(&[0xaa; 300]);
+        content.extend_from_slice(&[11, 0x00, 0x01, 0x7f]);
+
+        let items = KLVReader::with_length_mode(&content, LengthMode::TwoByteBE)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].key, 10);
+        assert_eq!(items[0].length, 300);
+        assert_eq!(items[0].value, Some(&[0xaa; 300][..]));
+        assert_eq!(items[1].key, 11);
+        assert_eq!(items[1].value, Some(&[0x7f][..]));
+
+        // 0x81は1Byte固定ならそのまま長さになる
+        let mut content = vec![10, 0x81];
+        content.extend_from_slice(&[0xbb; 0x81]);
+        let spans = KLVReader::with_length_mode(&content, LengthMode::OneByte)
+            .spans()
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec![(10, 2, 0x81)]);
+    }
+
+    #[test]
+    fn test_reader_remaining() {
+        let content = &SINGLE_SET[17..];
+        let mut reader = KLVReader::from_bytes(content);
+        assert_eq!(reader.remaining(), content);
+        for x in reader.by_ref() {
+            x.unwrap();
+        }
+        assert!(reader.remaining().is_empty());
+
+        let mut content = content.to_vec();
+        content.push(12);
+        let mut reader = KLVReader::from_bytes(&content);
+        assert!(reader.by_ref().last().unwrap().is_err());
+        assert_eq!(reader.remaining(), &[12]);
+    }
+
+    #[test]
+    fn test_reader_max_item_len() {
+        // 10MBを宣言するitem
+        let content = [10, 1, 0x7f, 11, 0x84, 0x00, 0xa0, 0x00, 0x00, 0x01];
+        let mut reader = KLVReader::from_bytes(&content).with_max_item_len(1024);
+        assert_eq!(reader.next().unwrap().unwrap().key, 10);
+        match reader.next() {
+            Some(Err(Error::ItemTooLong { tag, len, max })) => {
+                assert_eq!((tag, len, max), (11, 0xa0_0000, 1024))
+            }
+            x => unreachable!("{:?}", x),
+        }
+        assert!(reader.next().is_none());
+        assert_eq!(reader.remaining(), &content[3..]);
+    }
+
+    #[test]
+    fn test_reader_for_each_with_offset() {
+        let content = [10, 1, 0x7f, 11, 2, 0x01, 0x02, 12, 3, 0x01];
+        let mut res = vec![];
+        KLVReader::from_bytes(&content).for_each_with_offset(|offset, key, x| {
+            res.push((offset, key, x.is_ok()));
+        });
+        assert_eq!(res, vec![(0, 10, true), (3, 11, true), (7, 12, false)]);
+    }
+
+    #[test]
+    fn test_raw_key_and_content() {
+        let content = [10, 2, 0x01, 0x02, 11, 0];
+        let items = KLVReader::from_bytes(&content)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(items[0].key_and_content(), (10, &[0x01, 0x02][..]));
+        assert_eq!(items[1].key_and_content(), (11, &[][..]));
+    }
+
+    #[test]
+    fn test_raw_nested_reader() {
+        let content = [48, 6, 1, 1, 0x7f, 2, 1, 0x03, 65, 1, 1];
+        let nested = {
+            let mut reader = KLVReader::from_bytes(&content);
+            let parent = reader.next().unwrap().unwrap();
+            assert_eq!(parent.key, 48);
+            parent.nested_reader()
+        };
+        // 親のitemより長く、元のbufferと同じ期間だけ生存できる
+        let items = nested.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(items[0].key_and_content(), (1, &[0x7f][..]));
+        assert_eq!(items[1].key_and_content(), (2, &[0x03][..]));
+        assert!(std::ptr::eq(items[0].value.unwrap(), &content[4..5]));
+    }
+
+    #[test]
+    fn test_map_eq() {
+        let other = SINGLE_SET.to_vec();
+        let a = KLVMap::try_from_bytes(SINGLE_SET).unwrap();
+        let b = KLVMap::try_from_bytes(&other).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.clone(), b);
+        assert_eq!(a.as_bytes(), SINGLE_SET);
+        let items = a.iter().copied().collect::<Vec<_>>();
+        assert_eq!(items, b.iter().copied().collect::<Vec<_>>());
+
+        let mut other = other;
+        *other.last_mut().unwrap() = 0xff;
+        let c = KLVMap::try_from_bytes(&other).unwrap();
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_reader_resync() {
+        // 11のLengthが壊れている
+        let content = [
+            10, 1, 0x7f, 11, 0x7f, 0x01, 0x02, 12, 1, 0x05, 13, 2, 0x00, 0x01,
+        ];
+        let items = KLVReader::from_bytes(&content).collect::<Vec<_>>();
+        assert_eq!(items.len(), 2);
+        assert!(items[1].is_err());
+
+        let items = KLVReader::from_bytes(&content)
+            .resync(&[10, 11, 12, 13])
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].as_ref().unwrap().key, 10);
+        assert!(items[1].is_err());
+        assert_eq!(
+            items[2].as_ref().unwrap().key_and_content(),
+            (12, &[0x05][..])
+        );
+        assert_eq!(
+            items[3].as_ref().unwrap().key_and_content(),
+            (13, &[0x00, 0x01][..])
+        );
+    }
+
+    #[test]
+    fn test_non_minimal_length() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test<'a> {
+            #[serde(rename = "10")]
+            s: &'a str,
+        }
+
+        // セットのLengthもitemのLengthも冗長なLong formで書かれている
+        let mut buf = b"TESTDATA00000000".to_vec();
+        buf.extend_from_slice(&[0x82, 0x00, 0x09, 10, 0x82, 0x00, 0x05]);
+        buf.extend_from_slice(b"hello");
+
+        let item = KLVReader::from_bytes(&buf[19..]).next().unwrap().unwrap();
+        assert_eq!(item.key_and_content(), (10, &b"hello"[..]));
+
+        let x = KLVMap::try_from_bytes(&buf).unwrap();
+        assert_eq!(x.content_len(), 9);
+        assert_eq!(x.iter().next().unwrap().length, 5);
+        assert!(validate_single_set(&buf).is_ok());
+        assert_eq!(checksum_range(&buf, 10), Some(0..23));
+
+        let x = from_bytes::<Test>(&buf).unwrap();
+        assert_eq!(x, Test { s: "hello" });
+    }
+
+    #[test]
+    fn test_reader_forced_4byte_ber() {
+        // 1Byteと4ByteのLengthが混在する
+        let content = [
+            10, 1, 0x7f, //
+            11, 0x84, 0x00, 0x00, 0x00, 0x02, 0x01, 0x02, //
+            12, 0x84, 0x00, 0x00, 0x00, 0x00, //
+            13, 1, 0x03,
+        ];
+        let items = KLVReader::with_length_mode(&content, LengthMode::Ber)
+            .map(|x| x.map(|x| (x.key, x.position, x.length)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            items,
+            vec![(10, 2, 1), (11, 9, 2), (12, 17, 0), (13, 19, 1)]
+        );
+    }
+
+    #[test]
+    fn test_map_key_matches_ignoring_version() {
+        let x = KLVMap::try_from_bytes(SINGLE_SET).unwrap();
+        let mut key = *b"TESTDATA00000000";
+        assert!(x.key_matches_ignoring_version(&key));
+        key[7] = b'B';
+        assert!(x.key_matches_ignoring_version(&key));
+        key[8] = b'1';
+        assert!(!x.key_matches_ignoring_version(&key));
+    }
+
+    #[test]
+    fn test_unknown_tag_message() {
+        // 未定義のKeyはserdeのdeny_unknown_fieldsで有効なKeyと一緒に報告される
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(rename = "TESTDATA00000000", deny_unknown_fields)]
+        struct Test {
+            #[serde(rename = "10")]
+            _a: u8,
+            #[serde(rename = "12")]
+            _b: u8,
+        }
+        match from_bytes::<Test>(SINGLE_SET) {
+            Err(Error::Message(msg)) => {
+                assert_eq!(msg, "unknown field `11`, expected `10` or `12`")
+            }
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_content_eq_ignoring() {
+        let a = [
+            2, 2, 0x00, 0x01, 10, 1, 0x7f, 11, 2, 0x01, 0x02, 1, 2, 0xaa, 0xaa,
+        ];
+        // timestampとchecksumが異なり、順序も異なる
+        let b = [
+            11, 2, 0x01, 0x02, 2, 2, 0x00, 0x02, 10, 1, 0x7f, 1, 2, 0xbb, 0xbb,
+        ];
+        assert!(!content_eq_ignoring(&a, &b, &[]));
+        assert!(!content_eq_ignoring(&a, &b, &[1]));
+        assert!(content_eq_ignoring(&a, &b, &[1, 2]));
+
+        // 値が異なる
+        let c = [2, 2, 0x00, 0x01, 10, 1, 0x7e, 11, 2, 0x01, 0x02];
+        assert!(!content_eq_ignoring(&a, &c, &[1, 2]));
+        // 不正なitem
+        assert!(!content_eq_ignoring(&a, &a[..a.len() - 1], &[]));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let a = [
+            2, 2, 0x00, 0x01, 10, 1, 0x7f, 11, 2, 0x01, 0x02, 1, 2, 0xaa, 0xaa,
+        ];
+        let b = [
+            11, 2, 0x01, 0x02, 2, 2, 0x00, 0x01, 10, 1, 0x7f, 1, 2, 0xbb, 0xbb,
+        ];
+        assert_ne!(content_hash(&a, &[]), content_hash(&b, &[]));
+        assert_eq!(content_hash(&a, &[1]), content_hash(&b, &[1]));
+        // 実行ごとに変わらない
+        assert_eq!(content_hash(&[], &[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(&[10, 1, 0x7f], &[]), 0x6042_d6ef_4215_3f8f);
+
+        // itemの区切りが異なる
+        let c = [10, 2, 0x01, 0x02, 11, 0];
+        let d = [10, 1, 0x01, 11, 1, 0x02];
+        assert_ne!(content_hash(&c, &[]), content_hash(&d, &[]));
+    }
+
+    #[test]
+    fn test_reader_group_by_tag() {
+        let content = [
+            10, 1, 0x01, 20, 1, 0xff, 10, 2, 0x02, 0x03, 10, 0, 20, 1, 0xfe,
+        ];
+        let map = KLVReader::from_bytes(&content).group_by_tag();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[&10], vec![&[0x01][..], &[0x02, 0x03][..], &[][..]]);
+        assert_eq!(map[&20], vec![&[0xff][..], &[0xfe][..]]);
+    }
+
+    #[test]
+    fn test_raw_try_from_bytes() {
+        let x = KLVRaw::try_from_bytes(&[11, 2, 0x01, 0x02, 0xff]).unwrap();
+        assert_eq!(x.key_and_content(), (11, &[0x01, 0x02][..]));
+        assert_eq!(x.position, 2);
+        let x = KLVRaw::try_from_bytes(&[11, 0]).unwrap();
+        assert_eq!(x.key_and_content(), (11, &[][..]));
+
+        for buf in [&[][..], &[11], &[11, 2, 0x01]] {
+            match KLVRaw::try_from_bytes(buf) {
+                Err(Error::ContentLenght) => {}
+                x => unreachable!("{:?}", x),
+            }
+        }
+    }
+
+    #[test]
+    fn test_peek_header() {
+        let (key, content_len, header_len) = peek_header(SINGLE_SET).unwrap();
+        assert_eq!(&key, b"TESTDATA00000000");
+        assert_eq!((content_len, header_len), (7, 17));
+
+        // Long formでcontentが揃っていなくても読める
+        let mut buf = b"TESTDATA00000000".to_vec();
+        buf.extend_from_slice(&[0x82, 0x01, 0x00, 10]);
+        let (_, content_len, header_len) = peek_header(&buf).unwrap();
+        assert_eq!((content_len, header_len), (256, 19));
+
+        assert!(peek_header(&SINGLE_SET[..16]).is_err());
+        assert!(peek_header(&buf[..18]).is_err());
+    }
+
+    #[test]
+    fn test_i8_length() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test {
+            #[serde(rename = "10")]
+            i8: i8,
+        }
+        let buf = crate::frame(b"TESTDATA00000000", &[10, 1, 0xfe]);
+        assert_eq!(from_bytes::<Test>(&buf).unwrap(), Test { i8: -2 });
+
+        // i8のitemは1Byteでなければならない
+        let buf = crate::frame(b"TESTDATA00000000", &[10, 2, 0xff, 0xfe]);
+        match from_bytes::<Test>(&buf) {
+            Err(Error::TypeLength(_)) => {}
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_indefinite_reserved_length() {
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test {
+            #[serde(rename = "10")]
+            _a: u8,
+        }
+        let mut buf = b"TESTDATA00000000".to_vec();
+        buf.extend_from_slice(&[0x80, 10, 1, 0x01]);
+        match from_bytes::<Test>(&buf) {
+            Err(Error::IndefiniteLength) => {}
+            x => unreachable!("{:?}", x),
+        }
+        // 読み飛ばすitem
+        let buf = crate::frame(b"TESTDATA00000000", &[11, 0xff, 0x01, 10, 1, 0x01]);
+        match from_bytes::<Test>(&buf) {
+            Err(Error::ReservedLength) => {}
+            x => unreachable!("{:?}", x),
+        }
+
+        // KLVReaderも同じエラーを返す
+        let mut reader = KLVReader::from_bytes(&[10, 1, 0x01, 11, 0x80, 0x01]);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(Error::IndefiniteLength))));
+        let mut reader = KLVReader::from_bytes(&[11, 0xff, 0x01]);
+        assert!(matches!(reader.next(), Some(Err(Error::ReservedLength))));
+    }
+
+    #[test]
+    fn test_small_klv_index() {
+        let content = [10, 1, 0x7f, 11, 2, 0x01, 0x02, 12, 0];
+        let index = SmallKlvIndex::<4>::try_from_content(&content).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.get(11), Some((5, 2)));
+        assert_eq!(&content[5..7], &[0x01, 0x02]);
+        assert_eq!(index.get(13), None);
+        assert_eq!(
+            index.iter().map(|x| x.0).collect::<Vec<_>>(),
+            vec![10, 11, 12]
+        );
+        assert!(SmallKlvIndex::<0>::try_from_content(&[])
+            .unwrap()
+            .is_empty());
+
+        // N件を超える
+        match SmallKlvIndex::<2>::try_from_content(&content) {
+            Err(Error::Message(_)) => {}
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_absent_option_is_none() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test {
+            #[serde(rename = "10")]
+            a: u8,
+            #[serde(rename = "11")]
+            b: Option<u16>,
+            #[serde(rename = "12")]
+            c: Option<String>,
+            #[serde(rename = "13")]
+            d: Option<u8>,
+        }
+        // 11と12はKeyごと無く、長さ0のitemも無い
+        let buf = crate::frame(b"TESTDATA00000000", &[10, 1, 0x01, 13, 1, 0x02]);
+        assert_eq!(
+            from_bytes::<Test>(&buf).unwrap(),
+            Test {
+                a: 1,
+                b: None,
+                c: None,
+                d: Some(2),
+            }
+        );
+    }
+
+    #[test]
+    fn test_find_duplicate_tags() {
+        let content = [10, 1, 0x01, 11, 1, 0x02, 12, 0, 10, 1, 0x03];
+        let reader = KLVReader::from_bytes(&content);
+        assert_eq!(reader.find_duplicate_tags(), vec![10]);
+        // readerは進まない
+        assert_eq!(reader.count(), 4);
+
+        assert!(KLVReader::from_bytes(&content[..8])
+            .find_duplicate_tags()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_nested_struct_borrowed_str() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Parent<'a> {
+            #[serde(rename = "10")]
+            a: u8,
+            #[serde(rename = "48", borrow)]
+            inner: Inner<'a>,
+            #[serde(rename = "11")]
+            b: u8,
+        }
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        #[serde(rename = "INNER")]
+        struct Inner<'a> {
+            #[serde(rename = "3")]
+            name: &'a str,
+            #[serde(rename = "4")]
+            v: u16,
+        }
+        let inner = [3, 5, b'h', b'e', b'l', b'l', b'o', 4, 2, 0x12, 0x34];
+        let mut content = vec![10, 1, 0x01, 48, inner.len() as u8];
+        content.extend_from_slice(&inner);
+        content.extend_from_slice(&[11, 1, 0x02]);
+        let buf = crate::frame(b"TESTDATA00000000", &content);
+
+        // Deserializerが破棄された後もbufを借用している
+        let name = {
+            let x = from_bytes::<Parent>(&buf).unwrap();
+            assert_eq!(
+                x,
+                Parent {
+                    a: 1,
+                    inner: Inner {
+                        name: "hello",
+                        v: 0x1234
+                    },
+                    b: 2,
+                }
+            );
+            x.inner.name
+        };
+        assert!(buf.as_ptr_range().contains(&name.as_ptr()));
+
+        // 埋め込まれたセットのLengthがbufを超える
+        let mut broken = buf.clone();
+        broken[17 + 4] = 0x7f;
+        assert!(from_bytes::<Parent>(&broken).is_err());
+    }
+
+    #[test]
+    fn test_map_lenient() {
+        let mut buf = crate::frame(b"TESTDATA00000000", &[10, 1, 0x01, 11, 2, 0x02, 0x03]);
+        let x = KLVMap::try_from_bytes_lenient(&buf).unwrap();
+        assert!(!x.length_corrected());
+        assert_eq!(x, KLVMap::try_from_bytes(&buf).unwrap());
+
+        // contentのLengthが3Byte多い
+        buf[16] += 3;
+        assert!(KLVMap::try_from_bytes(&buf).is_err());
+        let x = KLVMap::try_from_bytes_lenient(&buf).unwrap();
+        assert!(x.length_corrected());
+        assert_eq!(x.content_len(), 7);
+        assert_eq!(x.iter().map(|x| x.key).collect::<Vec<_>>(), vec![10, 11]);
+
+        // 末尾のitemが欠けている
+        let x = KLVMap::try_from_bytes_lenient(&buf[..buf.len() - 1]).unwrap();
+        assert!(x.length_corrected());
+        assert_eq!(x.iter().map(|x| x.key).collect::<Vec<_>>(), vec![10]);
+    }
+
+    #[test]
+    fn test_owned_set() {
+        let buf = crate::frame(
+            b"TESTDATA00000000",
+            &[10, 1, 0x01, 11, 0, 12, 3, b'a', b'b', b'c', 10, 1, 0x02],
+        );
+        let set = OwnedSet::decode(&buf).unwrap();
+        assert_eq!(&set.key, b"TESTDATA00000000");
+        assert_eq!(
+            set.items,
+            vec![
+                (10, vec![0x01]),
+                (11, vec![]),
+                (12, b"abc".to_vec()),
+                (10, vec![0x02])
+            ]
+        );
+        assert_eq!(set.encode(), buf);
+        assert!(OwnedSet::decode(&buf[..buf.len() - 1]).is_err());
+
+        // serdeの他の形式を経由する
+        let json = serde_json::to_vec(&set).unwrap();
+        let x = serde_json::from_slice::<OwnedSet>(&json).unwrap();
+        assert_eq!(x, set);
+        assert_eq!(x.encode(), buf);
+    }
+
+    #[test]
+    fn test_reader_content_end() {
+        // contentは7Byteで、その後に別のデータが続く
+        let buf = [10, 1, 0x7f, 11, 5, 0x01, 0x02, 0xaa, 0xbb, 0xcc];
+        let items = KLVReader::from_bytes(&buf).collect::<Vec<_>>();
+        assert!(items.iter().all(|x| x.is_ok()));
+
+        let mut reader = KLVReader::from_bytes(&buf).with_content_end(7);
+        assert_eq!(reader.next().unwrap().unwrap().key, 10);
+        match reader.next() {
+            Some(Err(Error::ContentLenght)) => {}
+            x => unreachable!("{:?}", x),
+        }
+        assert!(reader.next().is_none());
+        assert_eq!(reader.remaining(), &buf[3..7]);
+    }
+
+    #[test]
+    fn test_value_overruns_set() {
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test<'a> {
+            #[serde(rename = "10", borrow)]
+            _a: &'a [u8],
+        }
+        // セットのLengthは4だが、itemは5Byteを宣言して後続のデータまで読む
+        let mut buf = b"TESTDATA00000000".to_vec();
+        buf.extend_from_slice(&[4, 10, 5, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        match from_bytes::<Test>(&buf) {
+            Err(Error::ContentLenght) => {}
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_pack_reader() {
+        // VMTI Target Seriesの2つのVTarget Pack
+        let buf = [
+            0x05, 0x01, 0x02, 0x02, 0x11, 0x22, // target 1
+            0x03, 0x02, 0x05, 0x00, // target 2
+        ];
+        let packs = PackReader::from_bytes(&buf)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(packs, vec![&buf[1..6], &buf[7..10]]);
+        // 各Packの先頭はTarget ID(BER-OID)で、続くitemはKLVReaderで読める
+        let items = KLVReader::from_bytes(&packs[0][1..])
+            .map(|x| x.unwrap().key_and_content())
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![(2, &[0x11, 0x22][..])]);
+
+        assert_eq!(PackReader::from_bytes(&[]).count(), 0);
+        // 2つ目のPackが途中で切れている
+        let mut reader = PackReader::from_bytes(&buf[..9]);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(Error::ContentLenght))));
+        assert!(reader.next().is_none());
+    }
+}
@@ -17,6 +17,12 @@ pub enum Error {
     Key(String),
     // Unsupported Length, define by BER encoding rules
     UnsupportedLength(String),
+    // 不定長(0x80)のLength。KLVでは使わない
+    IndefiniteLength,
+    // 予約済み(0xff)のLength
+    ReservedLength,
+    // `KLVReader::with_max_item_len` の上限を超えるLength
+    ItemTooLong { tag: u8, len: usize, max: usize },
     // write bytes
     IO(std::io::Error),
     // byte encoding
@@ -30,6 +36,8 @@ pub enum Error {
     ExpectedString,
     // unmatch length between length and content
     ExpectedMapEnd,
+    // buffer has extra bytes after the framed set
+    TrailingBytes(usize),
 }
 
 impl ser::Error for Error {
@@ -49,6 +57,17 @@ impl Display for Error {
         match self {
             Error::Message(msg) => formatter.write_str(msg),
             Error::ContentLenght => formatter.write_str("unexpected end of input or less"),
+            Error::UnsupportedLength(msg) => write!(formatter, "unsupported length: {}", msg),
+            Error::IndefiniteLength => {
+                formatter.write_str("indefinite length (0x80) is not allowed")
+            }
+            Error::ReservedLength => formatter.write_str("reserved length octet (0xff)"),
+            Error::ItemTooLong { tag, len, max } => write!(
+                formatter,
+                "item length {} of tag {} exceeds limit {}",
+                len, tag, max
+            ),
+            Error::TrailingBytes(n) => write!(formatter, "{} trailing bytes after set", n),
             /* and so forth */
             _ => formatter.write_str("unexpected error"),
         }
@@ -0,0 +1,258 @@
+//! JSON export for unknown KLV set
+
+use serde_json::{Map, Value};
+
+use crate::de::KLVReader;
+use crate::error::{Error, Result};
+use crate::frame_iter;
+
+/// contentの全itemを `{ "<tag>": "<hex value>" }` に変換する
+///
+/// Local Setの定義を知らなくてもデータを失わないためのログ用途。
+/// 同じTagが複数回現れた場合は、そのTagの値を出現順の配列にする。
+/// 不正なitemに到達した場合はそれ以降を読まずに `"_error"` にエラー内容を入れる
+pub fn to_json_hex(content: &[u8]) -> Value {
+    let mut map = Map::new();
+    for x in KLVReader::from_bytes(content) {
+        match x {
+            Ok(x) => {
+                let hex = x
+                    .value
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>();
+                let hex = Value::String(hex);
+                match map.get_mut(&x.key.to_string()) {
+                    Some(Value::Array(xs)) => xs.push(hex),
+                    Some(prev) => *prev = Value::Array(vec![prev.take(), hex]),
+                    None => {
+                        map.insert(x.key.to_string(), hex);
+                    }
+                }
+            }
+            Err(e) => {
+                map.insert("_error".to_string(), Value::String(format!("{:?}", e)));
+            }
+        }
+    }
+    Value::Object(map)
+}
+
+/// `{ "<tag>": <value> }` のobjectからKLVセットを組み立てる
+///
+/// Keyは0..=255の数値の文字列で、itemはKeyの昇順に並べる。valueは次の規則でbyte列にする。
+///
+/// - 0以上の整数: 収まる最小の幅(1, 2, 4, 8Byte)の符号なしBigEndian
+/// - 負の整数: 収まる最小の幅(1, 2, 4, 8Byte)の符号付きBigEndian
+/// - 小数: 8ByteのIEEE 754
+/// - `"0x"` で始まる文字列: 続く16進数をbyte列にする
+/// - その他の文字列: UTF-8
+/// - bool: 1Byteの0または1
+/// - null: itemを書き込まない
+/// - `{"<型>": <数値>}`: 型の幅で書き込む。型は `u8`, `u16`, `u32`, `u64`, `i8`, `i16`, `i32`, `i64`, `f32`, `f64`
+///
+/// 固定幅のTagに書き込む場合は、`{"u16": 100}` のように型を指定する
+pub fn from_json(key: &[u8; 16], obj: &Value) -> Result<Vec<u8>> {
+    let obj = obj
+        .as_object()
+        .ok_or_else(|| Error::Message("json value must be an object".to_string()))?;
+    let mut items = vec![];
+    for (k, v) in obj {
+        let tag = k
+            .parse::<u8>()
+            .map_err(|_| Error::Key(format!("tag must be a number in 0..=255, got {}", k)))?;
+        if let Some(value) = json_to_bytes(tag, v)? {
+            items.push((tag, value));
+        }
+    }
+    items.sort_by_key(|x| x.0);
+    Ok(frame_iter(key, items.iter().map(|(k, v)| (*k, &v[..]))))
+}
+
+fn json_to_bytes(tag: u8, v: &Value) -> Result<Option<Vec<u8>>> {
+    let bytes = match v {
+        Value::Null => return Ok(None),
+        Value::Bool(x) => vec![*x as u8],
+        Value::Number(x) => {
+            if let Some(x) = x.as_u64() {
+                match x {
+                    0..=0xff => vec![x as u8],
+                    0x100..=0xffff => (x as u16).to_be_bytes().to_vec(),
+                    0x1_0000..=0xffff_ffff => (x as u32).to_be_bytes().to_vec(),
+                    _ => x.to_be_bytes().to_vec(),
+                }
+            } else if let Some(x) = x.as_i64() {
+                if let Ok(x) = i8::try_from(x) {
+                    x.to_be_bytes().to_vec()
+                } else if let Ok(x) = i16::try_from(x) {
+                    x.to_be_bytes().to_vec()
+                } else if let Ok(x) = i32::try_from(x) {
+                    x.to_be_bytes().to_vec()
+                } else {
+                    x.to_be_bytes().to_vec()
+                }
+            } else {
+                x.as_f64().unwrap_or_default().to_be_bytes().to_vec()
+            }
+        }
+        Value::String(x) => match x.strip_prefix("0x") {
+            Some(hex) => decode_hex(hex)
+                .ok_or_else(|| Error::Encode(format!("tag {}: invalid hex string {}", tag, x)))?,
+            None => x.as_bytes().to_vec(),
+        },
+        Value::Object(x) if x.len() == 1 => {
+            let (ty, n) = x.iter().next().unwrap();
+            typed_number(ty, n)
+                .ok_or_else(|| Error::Encode(format!("tag {}: invalid {} value {}", tag, ty, n)))?
+        }
+        _ => {
+            return Err(Error::Encode(format!(
+                "tag {}: unsupported json value {}",
+                tag, v
+            )))
+        }
+    };
+    Ok(Some(bytes))
+}
+
+// 型名の幅で数値を書き込む。型が不明か値が範囲外ならNone
+fn typed_number(ty: &str, n: &Value) -> Option<Vec<u8>> {
+    let bytes = match ty {
+        "u8" => u8::try_from(n.as_u64()?).ok()?.to_be_bytes().to_vec(),
+        "u16" => u16::try_from(n.as_u64()?).ok()?.to_be_bytes().to_vec(),
+        "u32" => u32::try_from(n.as_u64()?).ok()?.to_be_bytes().to_vec(),
+        "u64" => n.as_u64()?.to_be_bytes().to_vec(),
+        "i8" => i8::try_from(n.as_i64()?).ok()?.to_be_bytes().to_vec(),
+        "i16" => i16::try_from(n.as_i64()?).ok()?.to_be_bytes().to_vec(),
+        "i32" => i32::try_from(n.as_i64()?).ok()?.to_be_bytes().to_vec(),
+        "i64" => n.as_i64()?.to_be_bytes().to_vec(),
+        "f32" => (n.as_f64()? as f32).to_be_bytes().to_vec(),
+        "f64" => n.as_f64()?.to_be_bytes().to_vec(),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|x| match x {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use crate::from_bytes;
+    use crate::json::{from_json, to_json_hex};
+
+    #[test]
+    fn test_to_json_hex_repeated_tag() {
+        let x = to_json_hex(&[10, 1, 0x01, 11, 2, 0xab, 0xcd, 10, 1, 0x02, 10, 0]);
+        assert_eq!(x, json!({"10": ["01", "02", ""], "11": "abcd"}));
+
+        // 途中で壊れていても読めた分は残す
+        let x = to_json_hex(&[10, 1, 0x01, 10, 1, 0x02, 12, 5]);
+        assert_eq!(x["10"], json!(["01", "02"]));
+        assert!(x["_error"].is_string());
+    }
+
+    #[test]
+    fn test_from_json() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test<'a> {
+            #[serde(rename = "2")]
+            timestamp: u64,
+            #[serde(rename = "3")]
+            mission: &'a str,
+            #[serde(rename = "5")]
+            heading: u16,
+            #[serde(rename = "13")]
+            lat: i32,
+            #[serde(rename = "20")]
+            ratio: f64,
+            #[serde(rename = "94", with = "serde_bytes")]
+            id: &'a [u8],
+            #[serde(rename = "99", default)]
+            none: Option<u8>,
+        }
+        let obj = json!({
+            "13": -1_000_000,
+            "2": 1_231_798_102_000_000u64,
+            "3": "MISSION01",
+            "5": 0x3d3b,
+            "20": 0.5,
+            "94": "0x0170f5",
+            "99": null,
+        });
+        let buf = from_json(b"TESTDATA00000000", &obj).unwrap();
+        let x = from_bytes::<Test>(&buf).unwrap();
+        assert_eq!(
+            x,
+            Test {
+                timestamp: 1_231_798_102_000_000,
+                mission: "MISSION01",
+                heading: 0x3d3b,
+                lat: -1_000_000,
+                ratio: 0.5,
+                id: &[0x01, 0x70, 0xf5],
+                none: None,
+            }
+        );
+        // Keyの昇順
+        assert_eq!(&buf[17..20], &[2, 8, 0x00]);
+
+        assert!(from_json(b"TESTDATA00000000", &json!({"256": 1})).is_err());
+        assert!(from_json(b"TESTDATA00000000", &json!({"1": "0xabc"})).is_err());
+        assert!(from_json(b"TESTDATA00000000", &json!({"1": [1, 2]})).is_err());
+        assert!(from_json(b"TESTDATA00000000", &json!([1])).is_err());
+    }
+
+    #[test]
+    fn test_from_json_typed() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test {
+            #[serde(rename = "5")]
+            heading: u16,
+            #[serde(rename = "13")]
+            lat: i32,
+            #[serde(rename = "21")]
+            range: u32,
+            #[serde(rename = "22")]
+            ratio: f32,
+        }
+        // 型の幅より小さい値
+        let obj = json!({
+            "5": {"u16": 100},
+            "13": {"i32": -5},
+            "21": {"u32": 7},
+            "22": {"f32": 0.5},
+        });
+        let buf = from_json(b"TESTDATA00000000", &obj).unwrap();
+        assert_eq!(&buf[17..21], &[5, 2, 0x00, 100]);
+        let x = from_bytes::<Test>(&buf).unwrap();
+        assert_eq!(
+            x,
+            Test {
+                heading: 100,
+                lat: -5,
+                range: 7,
+                ratio: 0.5,
+            }
+        );
+
+        // 範囲外、不明な型、複数の型
+        assert!(from_json(b"TESTDATA00000000", &json!({"5": {"u16": 0x10000}})).is_err());
+        assert!(from_json(b"TESTDATA00000000", &json!({"5": {"u8": -1}})).is_err());
+        assert!(from_json(b"TESTDATA00000000", &json!({"5": {"u24": 1}})).is_err());
+        assert!(from_json(b"TESTDATA00000000", &json!({"5": {"u8": 1, "u16": 1}})).is_err());
+    }
+}
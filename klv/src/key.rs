@@ -0,0 +1,196 @@
+//! SMPTE ST 336 Universal Keyの組み立て
+
+use crate::error::{Error, Result};
+
+/// Universal Keyの先頭4Byte。Object Identifier, UL Size, ISO, SMPTEで固定
+pub const UL_PREFIX: [u8; 4] = [0x06, 0x0e, 0x2b, 0x34];
+
+/// Version Numberの位置。ST 0601のリビジョン違いではここだけが異なる
+pub const VERSION_BYTE: usize = 7;
+
+/// Version Number以外の15Byteが一致するか
+///
+/// 長さが16Byteでなければ一致しない
+pub fn key_matches_ignoring_version(key: &[u8], other: &[u8; 16]) -> bool {
+    key.len() == 16
+        && key
+            .iter()
+            .zip(other.iter())
+            .enumerate()
+            .all(|(i, (a, b))| i == VERSION_BYTE || a == b)
+}
+
+/// 最初に `UL_PREFIX` が現れる位置からのbyte列を返す
+///
+/// 前段でMPEG-TSやPESのヘッダが取り除かれていない入力向け。見つからない場合はNone
+pub fn strip_to_universal_key(buf: &[u8]) -> Option<&[u8]> {
+    buf.windows(UL_PREFIX.len())
+        .position(|x| x == UL_PREFIX)
+        .map(|at| &buf[at..])
+}
+
+/// 16ByteのUniversal Keyをフィールドごとに組み立てる
+///
+/// ```
+/// use klv::UniversalKeyBuilder;
+///
+/// let key = UniversalKeyBuilder::new()
+///     .category(0x02)
+///     .registry(0x0b)
+///     .structure(0x01)
+///     .version(0x01)
+///     .item(&[0x0e, 0x01, 0x03, 0x01, 0x01])
+///     .build()
+///     .unwrap();
+/// assert_eq!(&key[..4], &[0x06, 0x0e, 0x2b, 0x34]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniversalKeyBuilder {
+    key: [u8; 16],
+    item_len: usize,
+}
+
+impl Default for UniversalKeyBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UniversalKeyBuilder {
+    pub fn new() -> Self {
+        let mut key = [0; 16];
+        key[..4].copy_from_slice(&UL_PREFIX);
+        Self { key, item_len: 0 }
+    }
+
+    /// 既存のKeyから作る。先頭4Byteが `UL_PREFIX` でなければエラー
+    pub fn from_key(key: &[u8; 16]) -> Result<Self> {
+        if key[..4] != UL_PREFIX {
+            return Err(Error::Key(format!(
+                "universal key prefix must be {:02x?}, got {:02x?}",
+                UL_PREFIX,
+                &key[..4]
+            )));
+        }
+        Ok(Self {
+            key: *key,
+            item_len: 0,
+        })
+    }
+
+    /// Category Designator (byte 5)。Groupsは0x02
+    pub fn category(mut self, x: u8) -> Self {
+        self.key[4] = x;
+        self
+    }
+
+    /// Registry Designator (byte 6)。BER Length/1Byte KeyのLocal Setは0x0b
+    pub fn registry(mut self, x: u8) -> Self {
+        self.key[5] = x;
+        self
+    }
+
+    /// Structure Designator (byte 7)
+    pub fn structure(mut self, x: u8) -> Self {
+        self.key[6] = x;
+        self
+    }
+
+    /// Version Number (byte 8)
+    pub fn version(mut self, x: u8) -> Self {
+        self.key[VERSION_BYTE] = x;
+        self
+    }
+
+    /// Item Designator (byte 9-16)。8Byteに満たない分は0で埋める
+    pub fn item(mut self, x: &[u8]) -> Self {
+        self.item_len = x.len();
+        let len = x.len().min(8);
+        self.key[8..8 + len].copy_from_slice(&x[..len]);
+        self.key[8 + len..].fill(0);
+        self
+    }
+
+    pub fn build(self) -> Result<[u8; 16]> {
+        if self.item_len > 8 {
+            return Err(Error::Key(format!(
+                "item designator must be 8 bytes or less, got {}",
+                self.item_len
+            )));
+        }
+        if self.key[4] == 0 {
+            return Err(Error::Key("category designator is not set".to_string()));
+        }
+        Ok(self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{key_matches_ignoring_version, strip_to_universal_key, UniversalKeyBuilder};
+
+    const ST0601_KEY: &[u8; 16] =
+        b"\x06\x0e\x2b\x34\x02\x0b\x01\x01\x0e\x01\x03\x01\x01\x00\x00\x00";
+
+    #[test]
+    fn test_build_st0601_key() {
+        let key = UniversalKeyBuilder::new()
+            .category(0x02)
+            .registry(0x0b)
+            .structure(0x01)
+            .version(0x01)
+            .item(&[0x0e, 0x01, 0x03, 0x01, 0x01])
+            .build()
+            .unwrap();
+        assert_eq!(&key, ST0601_KEY);
+
+        let key = UniversalKeyBuilder::from_key(ST0601_KEY)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(&key, ST0601_KEY);
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        let mut key = *ST0601_KEY;
+        key[0] = 0x07;
+        assert!(UniversalKeyBuilder::from_key(&key).is_err());
+
+        // category未設定
+        assert!(UniversalKeyBuilder::new().build().is_err());
+        // item designatorが長すぎる
+        assert!(UniversalKeyBuilder::new()
+            .category(0x02)
+            .item(&[1; 9])
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_key_matches_ignoring_version() {
+        let mut other = *ST0601_KEY;
+        other[7] = 0x02;
+        assert!(key_matches_ignoring_version(ST0601_KEY, &other));
+        assert!(key_matches_ignoring_version(&other, ST0601_KEY));
+
+        // Version Number以外が異なる
+        let mut other = *ST0601_KEY;
+        other[8] = 0x0f;
+        assert!(!key_matches_ignoring_version(ST0601_KEY, &other));
+        assert!(!key_matches_ignoring_version(&ST0601_KEY[..15], ST0601_KEY));
+    }
+
+    #[test]
+    fn test_strip_to_universal_key() {
+        assert_eq!(strip_to_universal_key(ST0601_KEY), Some(&ST0601_KEY[..]));
+
+        // PES headerが残っている
+        let mut buf = vec![0x00, 0x00, 0x01, 0xbd, 0x00, 0x20, 0x80, 0x00, 0x00];
+        buf.extend_from_slice(ST0601_KEY);
+        assert_eq!(strip_to_universal_key(&buf), Some(&ST0601_KEY[..]));
+
+        assert_eq!(strip_to_universal_key(&ST0601_KEY[1..]), None);
+        assert_eq!(strip_to_universal_key(&[0x06, 0x0e, 0x2b]), None);
+    }
+}
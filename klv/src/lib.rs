@@ -4,15 +4,39 @@ use std::fmt::Debug;
 
 use byteorder::ByteOrder;
 
+mod checksum;
 mod de;
 mod error;
+mod key;
 mod se;
+pub mod serde_helpers;
+pub mod testutil;
+mod value;
 
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+#[cfg(feature = "json")]
+pub mod json;
 #[cfg(feature = "uasdls")]
 pub mod uasdls;
+#[cfg(feature = "uasdls")]
+pub mod waypoint;
+
+pub use checksum::{bcc16, finalize_checksum, reserve_checksum, Bcc16, CHECKSUM_PLACEHOLDER};
+pub use de::{
+    checksum_range, content_contains_tag, content_eq_ignoring, content_hash, from_bytes,
+    peek_header, spawn_decode, tag_coverage, validate_single_set, KLVMap, KLVRaw, KLVReader,
+    LengthMode, OwnedSet, PackReader, SmallKlvIndex,
+};
+pub use key::{
+    key_matches_ignoring_version, strip_to_universal_key, UniversalKeyBuilder, UL_PREFIX,
+    VERSION_BYTE,
+};
+pub use se::{to_bytes, to_bytes_content_only, MultiSetWriter, Serializer};
+pub use value::{decode_float, decode_int, TimeScale, TimestampWindow};
 
-pub use de::{from_bytes, KLVMap, KLVRaw};
-pub use se::to_bytes;
+#[cfg(feature = "derive")]
+pub use klv_derive::{klv, Klv, KlvTag};
 
 type LengthByteSize = usize;
 type ContentByteSize = usize;
@@ -20,23 +44,102 @@ type ContentByteSize = usize;
 /// parse length rule by BER
 pub fn parse_length(buf: &[u8]) -> Result<(LengthByteSize, ContentByteSize), String> {
     use byteorder::BigEndian;
+    if buf.is_empty() {
+        return Err("length octet is empty".to_string());
+    }
     match LengthOctet::from_u8(buf[0]) {
         LengthOctet::Short(x) => Ok((1, x as usize)),
+        LengthOctet::Long(x) if buf.len() <= x as usize => Err(format!(
+            "length octets are short, expect {} got {}",
+            x,
+            buf.len() - 1
+        )),
         LengthOctet::Long(x) => match x {
             1 => Ok((2, buf[1] as usize)),
             2 => Ok((3, BigEndian::read_u16(&buf[1..3]) as usize)),
-            4 => Ok((4, BigEndian::read_u32(&buf[1..5]) as usize)),
-            8 => Ok((4, BigEndian::read_u64(&buf[1..9]) as usize)),
+            4 => Ok((5, BigEndian::read_u32(&buf[1..5]) as usize)),
+            8 => Ok((9, BigEndian::read_u64(&buf[1..9]) as usize)),
             x => Err(format!(
                 "Unsupported length [{}], supported only {{1,2,4,8}}",
                 x
             )),
         },
-        LengthOctet::Indefinite => Err("length is Indefinete".to_string()),
-        LengthOctet::Reserved => Err("Reserved octet".to_string()),
+        LengthOctet::Indefinite => {
+            Err("indefinite length (0x80) is not allowed in KLV".to_string())
+        }
+        LengthOctet::Reserved => Err("reserved length octet (0xff)".to_string()),
+    }
+}
+
+/// `parse_length` のエラーを `Error` にする
+///
+/// 不定長(0x80)と予約(0xff)はそれぞれ `Error::IndefiniteLength` と `Error::ReservedLength` にする
+pub fn ber_length(buf: &[u8]) -> error::Result<(LengthByteSize, ContentByteSize)> {
+    match buf.first().map(|x| LengthOctet::from_u8(*x)) {
+        Some(LengthOctet::Indefinite) => Err(error::Error::IndefiniteLength),
+        Some(LengthOctet::Reserved) => Err(error::Error::ReservedLength),
+        _ => parse_length(buf).map_err(error::Error::UnsupportedLength),
     }
 }
 
+/// `std::io::Read` からBERのLengthを読む
+///
+/// 1Byte目を読み、Long formであれば続くLengthも読み足す。戻り値は `parse_length` と同じ
+/// (読み込んだLengthのByte数, contentのByte数)。途中でEOFになった場合は `UnexpectedEof` を返す
+pub fn read_length<R: std::io::Read>(
+    r: &mut R,
+) -> std::io::Result<(LengthByteSize, ContentByteSize)> {
+    use std::io::{Error, ErrorKind};
+    let mut buf = [0; 9];
+    r.read_exact(&mut buf[..1])?;
+    let length_len = match LengthOctet::from_u8(buf[0]) {
+        LengthOctet::Long(x) if x as usize >= buf.len() => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported length [{}]", x),
+            ))
+        }
+        LengthOctet::Long(x) => 1 + x as usize,
+        _ => 1,
+    };
+    r.read_exact(&mut buf[1..length_len])?;
+    parse_length(&buf[..length_len]).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Universal KeyとcontentからKLVセットを組み立てる
+///
+/// contentは1Byte KeyのitemをつなげたものでBERのLengthはここで付与する。
+/// 結果は `KLVMap::try_from_bytes` や `from_bytes` でそのまま読み出せる
+pub fn frame(key: &[u8; 16], content: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16 + 5 + content.len());
+    buf.extend_from_slice(key);
+    LengthOctet::length_to_buf(&mut buf, content.len()).unwrap();
+    buf.extend_from_slice(content);
+    buf
+}
+
+/// Universal Keyと(Key, value)のitemを順に返すIteratorからKLVセットを組み立てる
+///
+/// itemを一度contentにまとめてから `frame` と同様にLengthを付与するので、事前に `Vec` を作る必要はない
+pub fn frame_iter<'a>(key: &[u8; 16], items: impl IntoIterator<Item = (u8, &'a [u8])>) -> Vec<u8> {
+    let mut content = vec![];
+    for (k, v) in items {
+        content.push(k);
+        LengthOctet::length_to_buf(&mut content, v.len()).unwrap();
+        content.extend_from_slice(v);
+    }
+    frame(key, &content)
+}
+
+/// 組み立て済みのKLVセットを連続するPES payloadに分割する
+///
+/// Universal KeyとLengthを持つのは最初のchunkだけで、受信側は順につなげて1つのセットに戻す。
+/// `max_payload` が0の場合はpanicする
+pub fn chunk_for_pes(klv: &[u8], max_payload: usize) -> Vec<Vec<u8>> {
+    assert!(max_payload > 0, "max_payload must be greater than 0");
+    klv.chunks(max_payload).map(|x| x.to_vec()).collect()
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum LengthOctet {
     // 7bit(127)以下の長さは1byteで表される
@@ -84,7 +187,11 @@ impl LengthOctet {
 #[cfg(test)]
 mod tests {
 
-    use crate::LengthOctet;
+    use crate::error::Error;
+    use crate::{
+        ber_length, chunk_for_pes, frame, frame_iter, parse_length, read_length, KLVMap,
+        LengthOctet,
+    };
 
     #[test]
     fn test_length_octets() {
@@ -104,4 +211,130 @@ mod tests {
             assert_eq!(lo, expect);
         }
     }
+
+    #[test]
+    fn test_parse_length() {
+        // 先頭に0を含む冗長なLong formも最短形式と同じ値になる
+        let td: [(&[u8], (usize, usize)); 5] = [
+            (&[0x05], (1, 5)),
+            (&[0x81, 0x05], (2, 5)),
+            (&[0x82, 0x00, 0x05], (3, 5)),
+            (&[0x84, 0x00, 0x00, 0x00, 0x05], (5, 5)),
+            (&[0x88, 0, 0, 0, 0, 0, 0, 0, 0x05], (9, 5)),
+        ];
+        for (buf, expect) in td {
+            assert_eq!(parse_length(buf).unwrap(), expect, "{:02x?}", buf);
+        }
+        assert_eq!(parse_length(&[0x82, 0x00, 0x7f]).unwrap(), (3, 127));
+    }
+
+    #[test]
+    fn test_parse_length_indefinite_reserved() {
+        assert!(parse_length(&[0x80, 0x05]).unwrap_err().contains("0x80"));
+        assert!(parse_length(&[0xff, 0x05]).unwrap_err().contains("0xff"));
+        assert!(matches!(
+            ber_length(&[0x80, 0x05]),
+            Err(Error::IndefiniteLength)
+        ));
+        assert!(matches!(ber_length(&[0xff]), Err(Error::ReservedLength)));
+        assert!(matches!(
+            ber_length(&[0x83, 0, 0, 0]),
+            Err(Error::UnsupportedLength(_))
+        ));
+        assert_eq!(ber_length(&[0x81, 0x80]).unwrap(), (2, 128));
+    }
+
+    #[test]
+    fn test_read_length() {
+        use std::io::{Cursor, ErrorKind};
+
+        let mut r = Cursor::new(vec![
+            0x05, 0x81, 0xc8, 0x82, 0x01, 0x00, 0x84, 0x00, 0x00, 0x00, 0x05, 0x80, 0x82, 0x01,
+        ]);
+        assert_eq!(read_length(&mut r).unwrap(), (1, 5));
+        assert_eq!(read_length(&mut r).unwrap(), (2, 200));
+        assert_eq!(read_length(&mut r).unwrap(), (3, 256));
+        assert_eq!(read_length(&mut r).unwrap(), (5, 5));
+        // 不定長は扱えない
+        assert_eq!(
+            read_length(&mut r).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        // Lengthの途中でEOF
+        assert_eq!(
+            read_length(&mut r).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            read_length(&mut r).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_frame() {
+        let key = b"TESTDATA00000000";
+        let content = [10, 1, 0x7f, 11, 2, 0x01, 0x02];
+        let buf = frame(key, &content);
+        assert_eq!(buf.len(), 16 + 1 + content.len());
+        let x = KLVMap::try_from_bytes(&buf).unwrap();
+        assert_eq!(x.universal_key(), key);
+        assert_eq!(x.content_len(), content.len());
+        assert_eq!(&buf[17..], &content);
+
+        // 128Byte以上はLong formになる
+        let content = [0; 200];
+        let buf = frame(key, &content);
+        assert_eq!(&buf[16..18], &[0x81, 200]);
+        assert_eq!(&buf[18..], &content);
+    }
+
+    #[test]
+    fn test_frame_iter() {
+        let key = b"TESTDATA00000000";
+        let long = [0xaa; 200];
+        let items = (10..13u8).map(|x| match x {
+            12 => (x, &long[..]),
+            _ => (x, &long[..x as usize - 9]),
+        });
+        let buf = frame_iter(key, items);
+
+        let mut content = vec![10, 1, 0xaa, 11, 2, 0xaa, 0xaa, 12, 0x81, 200];
+        content.extend_from_slice(&long);
+        assert_eq!(buf, frame(key, &content));
+        assert_eq!(frame_iter(key, []), frame(key, &[]));
+    }
+
+    #[test]
+    fn test_chunk_for_pes() {
+        let content = (0..481).map(|x| x as u8).collect::<Vec<_>>();
+        let klv = frame(b"TESTDATA00000000", &content);
+        assert_eq!(klv.len(), 500);
+        let chunks = chunk_for_pes(&klv, 200);
+        assert_eq!(
+            chunks.iter().map(|x| x.len()).collect::<Vec<_>>(),
+            vec![200, 200, 100]
+        );
+        assert_eq!(&chunks[0][..16], b"TESTDATA00000000");
+        assert_eq!(chunks.concat(), klv);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_klv_tag() {
+        use crate::KlvTag;
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, KlvTag)]
+        #[repr(u8)]
+        enum Tag {
+            Checksum = 1,
+            Timestamp,
+            LsVersion = 65,
+        }
+        assert_eq!(Tag::all(), &[Tag::Checksum, Tag::Timestamp, Tag::LsVersion]);
+        assert_eq!(Tag::try_from(2), Ok(Tag::Timestamp));
+        assert_eq!(Tag::try_from(3), Err(3));
+        assert_eq!(Tag::LsVersion.as_byte(), 65);
+        assert_eq!(Tag::Checksum.name(), "Checksum");
+    }
 }
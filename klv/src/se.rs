@@ -1,11 +1,12 @@
 use std::collections::BTreeSet;
+use std::ops::Range;
 
 use byteorder::{BigEndian, WriteBytesExt};
 use serde::{ser, Serialize};
 
 use crate::{
     error::{Error, Result},
-    LengthOctet,
+    Bcc16, LengthOctet,
 };
 
 pub struct Serializer {
@@ -13,41 +14,183 @@ pub struct Serializer {
     universal_key: Vec<u8>,
     output: Vec<u8>,
     keys: BTreeSet<u8>,
+    // serialize_noneが書き込んだLengthの位置
+    none_at: Option<usize>,
+    // serialize_u16が書き込んだLengthの位置
+    u16_at: Option<usize>,
+    // 書き込み中のTupleの要素の開始位置
+    tuple_at: Vec<usize>,
+    // 書き込んだitemのKeyとoutput上の範囲
+    items: Vec<(u8, Range<usize>)>,
+    sort_tags: bool,
+    embed_checksum: bool,
+    content_only: bool,
+    strict: bool,
+}
+
+impl Default for Serializer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Serializer {
+    pub fn new() -> Self {
+        Self {
+            universal_key: vec![],
+            output: vec![],
+            keys: BTreeSet::new(),
+            none_at: None,
+            u16_at: None,
+            tuple_at: vec![],
+            items: vec![],
+            sort_tags: false,
+            embed_checksum: false,
+            content_only: false,
+            strict: false,
+        }
+    }
+
+    /// itemをKeyの昇順で出力する。checksum(Key 1)は常に最後にする
+    pub fn sort_tags(mut self, sort_tags: bool) -> Self {
+        self.sort_tags = sort_tags;
+        self
+    }
+
+    /// checksum(Key 1)のvalueをBCC-16で上書きする
+    ///
+    /// checksumのitemは最後に移し、書き込みながら計算する。valueは2Byteである必要がある
+    pub fn embed_checksum(mut self, embed_checksum: bool) -> Self {
+        self.embed_checksum = embed_checksum;
+        self
+    }
+
+    /// Universal KeyとLengthを付けずにitemだけを出力する
+    ///
+    /// 親のitemのvalueとしてセットを埋め込む場合に使う。
+    /// checksumはUniversal KeyとLengthを含めて計算するため、`embed_checksum` とは併用できない
+    pub fn content_only(mut self, content_only: bool) -> Self {
+        self.content_only = content_only;
+        self
+    }
+
+    /// `embed_checksum` でKey 1をu16以外のフィールドが使っていればエラーにする
+    ///
+    /// checksumのフィールドは `UASDatalinkLS::checksum` のようにKey 1のu16とし、値は問わずに上書きする。
+    /// 他の用途のフィールドに `rename = "1"` を付けると、checksumで上書きされるか、
+    /// 2Byteでなければ上書きされずに出力されるため、どちらも誤りに気付けない。
+    /// Key 1が無い場合はchecksumを埋め込まないだけなのでエラーにしない
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn to_bytes<T>(mut self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        if self.content_only && self.embed_checksum {
+            return Err(Error::Encode(
+                "embed_checksum requires universal key and length, disable content_only"
+                    .to_string(),
+            ));
+        }
+        value.serialize(&mut self)?;
+        // ここでKeyを合成するのが良さそう
+        Ok(self.concat())
+    }
+
     fn concat(self) -> Vec<u8> {
         let Self {
             universal_key: mut key,
             output,
+            mut items,
+            sort_tags,
+            embed_checksum,
+            content_only,
             ..
         } = self;
-        LengthOctet::length_to_buf(&mut key, output.len()).unwrap();
-        key.extend_from_slice(&output);
+        if content_only {
+            key.clear();
+        } else {
+            LengthOctet::length_to_buf(&mut key, output.len()).unwrap();
+        }
+        if !sort_tags && !embed_checksum {
+            key.extend_from_slice(&output);
+            return key;
+        }
+        if sort_tags {
+            items.sort_by_key(|(k, _)| (*k == CHECKSUM_KEY, *k));
+        } else {
+            items.sort_by_key(|(k, _)| *k == CHECKSUM_KEY);
+        }
+        // 書き込みながらchecksumを計算する
+        let mut bcc = Bcc16::new();
+        bcc.update(&key);
+        for (k, range) in items {
+            let item = &output[range];
+            if embed_checksum && k == CHECKSUM_KEY && item.len() == 4 {
+                key.extend_from_slice(&item[..2]);
+                bcc.update(&item[..2]);
+                key.extend_from_slice(&bcc.finalize().to_be_bytes());
+            } else {
+                key.extend_from_slice(item);
+                bcc.update(item);
+            }
+        }
         key
     }
-    // TODO常にチェックサムを埋め込み、データ破損に対してロバストにする
-    #[allow(dead_code)]
-    fn checksum(buf: &[u8]) -> u32 {
-        buf.iter().fold(0, |a, x| a + *x as u32)
-    }
 }
 
+/// ST 0601のchecksumのKey
+const CHECKSUM_KEY: u8 = 1;
+
 pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>>
 where
     T: Serialize,
 {
-    let mut serializer = Serializer {
-        universal_key: vec![],
-        output: vec![],
-        keys: BTreeSet::new(),
-    };
-    value.serialize(&mut serializer)?;
-    // ここでKeyを合成するのが良さそう
-    Ok(serializer.concat())
+    Serializer::new().to_bytes(value)
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+/// Universal KeyとLengthを除いたitemだけを書き込む
+///
+/// `frame` でKeyを付ければ `to_bytes` と同じになる
+pub fn to_bytes_content_only<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    Serializer::new().content_only(true).to_bytes(value)
+}
+
+/// 複数のセットをそれぞれKeyとLength付きで1つのbufに続けて書き込む
+///
+/// 各セットは `to_bytes` と同じ形式なので、`peek_header` で長さを読みながら順に取り出せる
+#[derive(Debug, Default)]
+pub struct MultiSetWriter {
+    buf: Vec<u8>,
+}
+
+impl MultiSetWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// セットを1つ末尾に書き込む。エラーの場合は何も書き込まない
+    pub fn write_set<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let set = to_bytes(value)?;
+        self.buf.extend_from_slice(&set);
+        Ok(())
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl ser::Serializer for &mut Serializer {
     // io::Writeを想定するのが良い?
     type Ok = ();
 
@@ -105,6 +248,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
+        self.u16_at = Some(self.output.len());
         LengthOctet::length_to_buf(&mut self.output, 2).map_err(Error::IO)?;
         self.output
             .write_u16::<BigEndian>(v)
@@ -162,12 +306,14 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
+        // fieldの値がNoneであればSerializeStructでKeyごと取り除く
+        self.none_at = Some(self.output.len());
         self.serialize_unit()
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(self)
     }
@@ -190,18 +336,14 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         todo!()
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
-        self,
-        _name: &'static str,
-        _value: &T,
-    ) -> Result<Self::Ok>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, _value: &T) -> Result<Self::Ok>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         todo!()
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
@@ -209,7 +351,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         _value: &T,
     ) -> Result<Self::Ok>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         todo!()
     }
@@ -219,15 +361,17 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        unimplemented!()
+        // 各要素をLength付きで書き込み、endで全体のLengthを前に付ける
+        self.tuple_at.push(self.output.len());
+        Ok(self)
     }
 
     fn serialize_tuple_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        todo!()
+        self.serialize_tuple(len)
     }
 
     fn serialize_tuple_variant(
@@ -270,7 +414,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl ser::SerializeSeq for &mut Serializer {
     // Must match the `Ok` type of the serializer.
     type Ok = ();
     // Must match the `Error` type of the serializer.
@@ -290,7 +434,7 @@ impl<'a> ser::SerializeSeq for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl ser::SerializeTuple for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -302,11 +446,19 @@ impl<'a> ser::SerializeTuple for &'a mut Serializer {
     }
 
     fn end(self) -> Result<()> {
+        // 他のitemと同様に読み飛ばせるよう、要素全体をLengthで囲む
+        let start = self
+            .tuple_at
+            .pop()
+            .ok_or_else(|| Error::Encode("tuple is not started".to_string()))?;
+        let elements = self.output.split_off(start);
+        LengthOctet::length_to_buf(&mut self.output, elements.len()).map_err(Error::IO)?;
+        self.output.extend_from_slice(&elements);
         Ok(())
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl ser::SerializeTupleStruct for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -318,11 +470,11 @@ impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
     }
 
     fn end(self) -> Result<()> {
-        Ok(())
+        ser::SerializeTuple::end(self)
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl ser::SerializeTupleVariant for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -338,7 +490,7 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+impl ser::SerializeMap for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -361,7 +513,7 @@ impl<'a> ser::SerializeMap for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl ser::SerializeStruct for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -375,8 +527,27 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
         if !self.keys.insert(key) {
             return Err(Error::Key(format!("already use field {}", key)));
         }
+        let key_at = self.output.len();
         self.output.push(key);
-        value.serialize(&mut **self)
+        self.u16_at = None;
+        value.serialize(&mut **self)?;
+        // Noneは長さ0の値と区別できるようにKey自体を書き込まない
+        if self.none_at.take() == Some(key_at + 1) && self.output.len() == key_at + 2 {
+            self.output.truncate(key_at);
+            return Ok(());
+        }
+        if self.strict
+            && self.embed_checksum
+            && key == CHECKSUM_KEY
+            && self.u16_at.take() != Some(key_at + 1)
+        {
+            return Err(Error::Encode(format!(
+                "key {} is reserved for the u16 checksum field",
+                CHECKSUM_KEY
+            )));
+        }
+        self.items.push((key, key_at..self.output.len()));
+        Ok(())
     }
 
     fn end(self) -> Result<()> {
@@ -384,7 +555,7 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl ser::SerializeStructVariant for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -408,9 +579,12 @@ mod tests {
 
     use serde::{Deserialize, Serialize};
 
-    use crate::de::{from_bytes, KLVMap};
+    use crate::bcc16;
+    use crate::de::{checksum_range, from_bytes, KLVMap, KLVReader};
     use crate::error::Error;
-    use crate::se::to_bytes;
+    use crate::frame;
+    use crate::peek_header;
+    use crate::se::{to_bytes, to_bytes_content_only, MultiSetWriter, Serializer};
 
     /// シリアライズ、デシリアライズで対称性のある構造体
     #[test]
@@ -461,6 +635,8 @@ mod tests {
         assert_eq!(t, x);
     }
 
+    // 重複したKeyはDeserialize側で到達不能なパターンになるのでlintを抑制する
+    #[allow(unreachable_patterns)]
     #[test]
     fn test_serialize_error_by_key() {
         #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -541,6 +717,187 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_serialize_sort_tags() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestOrder {
+            #[serde(rename = "30")]
+            u8: u8,
+            #[serde(rename = "1")]
+            checksum: u16,
+            #[serde(rename = "10")]
+            u16: u16,
+            #[serde(rename = "2")]
+            none: Option<u8>,
+            #[serde(rename = "3")]
+            some: Option<u8>,
+        }
+        let t = TestOrder {
+            u8: 1,
+            checksum: 0x1c5f,
+            u16: 2,
+            none: None,
+            some: Some(3),
+        };
+        let s = Serializer::new().sort_tags(true).to_bytes(&t).unwrap();
+        assert_eq!(
+            &s[16..],
+            &[14, 3, 1, 3, 10, 2, 0, 2, 30, 1, 1, 1, 2, 0x1c, 0x5f]
+        );
+        let x = from_bytes::<TestOrder>(&s).unwrap();
+        assert_eq!(t, x);
+
+        // 指定しなければ宣言順
+        let s = to_bytes(&t).unwrap();
+        assert_eq!(&s[17..20], &[30, 1, 1]);
+    }
+
+    #[test]
+    fn test_serialize_embed_checksum() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestChecksum {
+            #[serde(rename = "1")]
+            checksum: u16,
+            #[serde(rename = "10")]
+            u16: u16,
+        }
+        let t = TestChecksum {
+            checksum: 0,
+            u16: 0x1234,
+        };
+        let s = Serializer::new().embed_checksum(true).to_bytes(&t).unwrap();
+        // checksumは最後に移る
+        assert_eq!(&s[16..21], &[8, 10, 2, 0x12, 0x34]);
+        assert_eq!(&s[21..23], &[1, 2]);
+        let range = checksum_range(&s, 1).unwrap();
+        assert_eq!(range, 0..23);
+        let x = from_bytes::<TestChecksum>(&s).unwrap();
+        assert_eq!(x.checksum, bcc16(&s[range]));
+        assert_eq!(x.u16, t.u16);
+    }
+
+    #[test]
+    fn test_serialize_strict_checksum() {
+        #[derive(Debug, Serialize)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test<T> {
+            #[serde(rename = "10")]
+            x: u8,
+            // checksumではないフィールドがKey 1を使っている
+            #[serde(rename = "1")]
+            other: T,
+        }
+        let t = Test {
+            x: 1,
+            other: "abc".to_string(),
+        };
+        let s = Serializer::new().embed_checksum(true);
+        assert!(matches!(s.strict(true).to_bytes(&t), Err(Error::Encode(_))));
+        // strictでなければそのまま出力する
+        let s = Serializer::new().embed_checksum(true);
+        assert!(s.to_bytes(&t).is_ok());
+        // checksumを埋め込まない場合は検査しない
+        let s = Serializer::new().strict(true);
+        assert!(s.to_bytes(&t).is_ok());
+
+        // 値が0の2Byteでもu16以外はchecksumのフィールドではない
+        let t = Test { x: 1, other: 0i16 };
+        let s = Serializer::new().embed_checksum(true).strict(true);
+        assert!(matches!(s.to_bytes(&t), Err(Error::Encode(_))));
+        let t = Test {
+            x: 1,
+            other: serde_bytes::Bytes::new(&[0, 0]),
+        };
+        let s = Serializer::new().embed_checksum(true).strict(true);
+        assert!(matches!(s.to_bytes(&t), Err(Error::Encode(_))));
+
+        // u16はchecksumのフィールドとして値を問わず上書きする
+        let t = Test {
+            x: 1,
+            other: 0x1234u16,
+        };
+        let s = Serializer::new()
+            .embed_checksum(true)
+            .strict(true)
+            .to_bytes(&t)
+            .unwrap();
+        assert_eq!(
+            s,
+            Serializer::new().embed_checksum(true).to_bytes(&t).unwrap()
+        );
+        assert_eq!(&s[s.len() - 2..], &bcc16(&s[..s.len() - 2]).to_be_bytes());
+
+        // Key 1が無ければchecksumを埋め込まないだけ
+        #[derive(Debug, Serialize)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct NoChecksum {
+            #[serde(rename = "10")]
+            x: u8,
+        }
+        let s = Serializer::new().embed_checksum(true).strict(true);
+        assert_eq!(
+            s.to_bytes(&NoChecksum { x: 1 }).unwrap(),
+            to_bytes(&NoChecksum { x: 1 }).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_serialize_content_only() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test {
+            #[serde(rename = "10")]
+            a: u8,
+            #[serde(rename = "11")]
+            b: u16,
+        }
+        let t = Test { a: 1, b: 0x1234 };
+        let content = to_bytes_content_only(&t).unwrap();
+        assert_eq!(content, [10, 1, 1, 11, 2, 0x12, 0x34]);
+
+        let s = frame(b"TESTDATA00000000", &content);
+        assert_eq!(s, to_bytes(&t).unwrap());
+        assert_eq!(from_bytes::<Test>(&s).unwrap(), t);
+
+        // checksumはframeした後のbyte列で計算するので埋め込めない
+        let s = Serializer::new().content_only(true).embed_checksum(true);
+        assert!(matches!(s.to_bytes(&t), Err(Error::Encode(_))));
+    }
+
+    #[test]
+    fn test_multi_set_writer() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test {
+            #[serde(rename = "10")]
+            a: u8,
+            #[serde(rename = "11")]
+            b: Option<u16>,
+        }
+        let sets = [
+            Test { a: 1, b: None },
+            Test { a: 2, b: Some(300) },
+            Test { a: 3, b: None },
+        ];
+        let mut w = MultiSetWriter::new();
+        for x in &sets {
+            w.write_set(x).unwrap();
+        }
+        let buf = w.into_bytes();
+
+        let mut decoded = vec![];
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            let (_, content_len, header_len) = peek_header(rest).unwrap();
+            let (set, next) = rest.split_at(header_len + content_len);
+            decoded.push(from_bytes::<Test>(set).unwrap());
+            rest = next;
+        }
+        assert_eq!(decoded, sets);
+    }
+
     #[test]
     fn test_serialize_str() {
         #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -607,8 +964,8 @@ mod tests {
             none_skip_some: Some("none skip".to_string()),
         };
         let s = to_bytes(&t).unwrap();
-        // skipしない場合はLength=0
-        assert!(find_subsequence(&s, &[32, 0]).is_some());
+        // skipしない場合もNoneはKey自体が存在しない
+        assert!(find_subsequence(&s, &[32, 0]).is_none());
         // skipする場合はKey自体が存在しない
         assert!(find_subsequence(&s, &[120, 0]).is_none());
         // データがある場合はskipされない
@@ -617,6 +974,75 @@ mod tests {
         assert_eq!(t, x);
     }
 
+    #[test]
+    fn test_serialize_empty_and_none() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestEmpty<'a> {
+            #[serde(
+                rename = "30",
+                borrow,
+                default,
+                deserialize_with = "crate::serde_helpers::option_str"
+            )]
+            empty: Option<&'a str>,
+            #[serde(
+                rename = "31",
+                borrow,
+                default,
+                deserialize_with = "crate::serde_helpers::option_str"
+            )]
+            none: Option<&'a str>,
+            #[serde(rename = "32")]
+            string: String,
+        }
+        let t = TestEmpty {
+            empty: Some(""),
+            none: None,
+            string: "".to_string(),
+        };
+        let s = to_bytes(&t).unwrap();
+        assert_eq!(&s[16..], &[4, 30, 0, 32, 0]);
+        let x = from_bytes::<TestEmpty>(&s).unwrap();
+        assert_eq!(t, x);
+    }
+
+    #[test]
+    fn test_deserialize_legacy_none() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestLegacy<'a> {
+            #[serde(rename = "30")]
+            number: Option<u16>,
+            #[serde(rename = "31")]
+            float: Option<f32>,
+            #[serde(rename = "32")]
+            text: Option<&'a str>,
+            #[serde(
+                rename = "33",
+                borrow,
+                default,
+                deserialize_with = "crate::serde_helpers::option_str"
+            )]
+            empty: Option<&'a str>,
+            #[serde(rename = "34")]
+            value: u8,
+        }
+        // 以前のエンコーダはNoneを長さ0で書き込んでいた
+        let s = crate::frame(b"TESTDATA00000000", &[30, 0, 31, 0, 32, 0, 33, 0, 34, 1, 7]);
+        let x = from_bytes::<TestLegacy>(&s).unwrap();
+        assert_eq!(
+            x,
+            TestLegacy {
+                number: None,
+                float: None,
+                text: None,
+                empty: Some(""),
+                value: 7,
+            }
+        );
+    }
+
     #[test]
     fn test_serialize_timestamp_micro() {
         #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -624,7 +1050,7 @@ mod tests {
         struct TestTimestamp<'a> {
             #[serde(rename = "30")]
             str: &'a str,
-            #[serde(rename = "31", with = "timestamp_micro")]
+            #[serde(rename = "31", with = "crate::serde_helpers::timestamp_micros")]
             ts: SystemTime,
         }
         let t = TestTimestamp {
@@ -645,6 +1071,44 @@ mod tests {
         assert_eq!(t_micros, x_micros);
     }
 
+    #[test]
+    fn test_serialize_duration_micros() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestDuration {
+            #[serde(rename = "30", with = "crate::serde_helpers::duration_micros")]
+            elapsed: Duration,
+        }
+        let t = TestDuration {
+            elapsed: Duration::from_micros(1_000_233_000),
+        };
+        let s = to_bytes(&t).unwrap();
+        assert_eq!(&s[17..19], &[30, 8]);
+        let x = from_bytes::<TestDuration>(&s).unwrap();
+        assert_eq!(t, x);
+    }
+
+    #[test]
+    fn test_serialize_duration() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestDuration {
+            #[serde(rename = "30", with = "crate::serde_helpers::duration")]
+            elapsed: Duration,
+            #[serde(rename = "31")]
+            u8: u8,
+        }
+        let t = TestDuration {
+            elapsed: Duration::new(1_000_233, 123_456_789),
+            u8: 127,
+        };
+        let s = to_bytes(&t).unwrap();
+        // 秒とナノ秒で12Byteになる
+        assert_eq!(&s[17..19], &[30, 12]);
+        let x = from_bytes::<TestDuration>(&s).unwrap();
+        assert_eq!(t, x);
+    }
+
     #[test]
     fn test_serialize_non_ascii_universal_key() {
         #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -661,6 +1125,58 @@ mod tests {
         assert_eq!(t, x);
     }
 
+    #[test]
+    fn test_serialize_tuple() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestTuple {
+            #[serde(rename = "10")]
+            tuple: (u8, u16, f32),
+            #[serde(rename = "11")]
+            u8: u8,
+        }
+        let t = TestTuple {
+            tuple: (8, 16, 0.5),
+            u8: 127,
+        };
+        let s = to_bytes(&t).unwrap();
+        // Tuple全体をLengthで囲み、その中に要素をLength付きで並べる
+        assert_eq!(
+            &s[17..],
+            &[10, 10, 1, 8, 2, 0, 16, 4, 0x3f, 0, 0, 0, 11, 1, 127]
+        );
+        let items = KLVReader::from_bytes(&s[17..])
+            .map(|x| x.map(|x| x.key))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(items, vec![10, 11]);
+        let x = from_bytes::<TestTuple>(&s).unwrap();
+        assert_eq!(t, x);
+    }
+
+    #[test]
+    fn test_serialize_tuple_struct() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Point(i16, i16);
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestTupleStruct {
+            #[serde(rename = "10")]
+            point: Point,
+            #[serde(rename = "11")]
+            u8: u8,
+        }
+        let t = TestTupleStruct {
+            point: Point(-1, 2),
+            u8: 127,
+        };
+        let s = to_bytes(&t).unwrap();
+        assert_eq!(&s[17..], &[10, 6, 2, 0xff, 0xff, 2, 0, 2, 11, 1, 127]);
+        let x = from_bytes::<TestTupleStruct>(&s).unwrap();
+        assert_eq!(t, x);
+    }
+
     #[test]
     fn test_serialize_bytes_any() {
         #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -739,7 +1255,7 @@ mod tests {
             str: &'a str,
             #[serde(rename = "61", with = "serde_bytes")]
             bytes: &'a [u8],
-            #[serde(rename = "62", with = "timestamp_micro")]
+            #[serde(rename = "62", with = "crate::serde_helpers::timestamp_micros")]
             ts: SystemTime,
         }
         let ts = SystemTime::UNIX_EPOCH
@@ -766,31 +1282,4 @@ mod tests {
             println!("{:?}", v);
         }
     }
-
-    mod timestamp_micro {
-        use std::time::{Duration, SystemTime};
-
-        use serde::{Deserialize, Deserializer, Serializer};
-
-        pub fn serialize<S>(date: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
-        where
-            S: Serializer,
-        {
-            let micros = date
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_micros();
-            serializer.serialize_u64(micros as u64)
-        }
-
-        pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
-        where
-            D: Deserializer<'de>,
-        {
-            let micros = u64::deserialize(deserializer)?;
-            SystemTime::UNIX_EPOCH
-                .checked_add(Duration::from_micros(micros))
-                .ok_or_else(|| serde::de::Error::custom("failed to deserialize systemtime"))
-        }
-    }
 }
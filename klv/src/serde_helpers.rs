@@ -0,0 +1,588 @@
+//! `#[serde(with = "...")]` で使う補助モジュール
+
+use byteorder::{BigEndian, ByteOrder};
+
+/// `SystemTime` をUNIX EPOCHからのマイクロ秒(u64)で表現する
+///
+/// ```ignore
+/// #[serde(rename = "2", with = "klv::serde_helpers::timestamp_micros")]
+/// timestamp: SystemTime,
+/// ```
+pub mod timestamp_micros {
+    use std::fmt;
+    use std::time::{Duration, SystemTime};
+
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    use crate::value::TimeScale;
+
+    pub fn serialize<S>(date: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let micros = date
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_micros();
+        serializer.serialize_u64(micros as u64)
+    }
+
+    /// 上位byteを省略して6, 7Byteで書き込むエンコーダ向けに、8Byteに満たない分は上位を0とみなす
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(MicrosVisitor)
+    }
+
+    struct MicrosVisitor;
+
+    impl<'de> Visitor<'de> for MicrosVisitor {
+        type Value = SystemTime;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("6 to 8 bytes of big endian microseconds")
+        }
+
+        fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            if !(6..=8).contains(&v.len()) {
+                return Err(E::invalid_length(v.len(), &self));
+            }
+            TimeScale::Micros
+                .decode(v)
+                .ok_or_else(|| E::custom("failed to deserialize systemtime"))
+        }
+
+        fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            SystemTime::UNIX_EPOCH
+                .checked_add(Duration::from_micros(v))
+                .ok_or_else(|| E::custom("failed to deserialize systemtime"))
+        }
+    }
+}
+
+/// `SystemTime` をUNIX EPOCHからのナノ秒(u64)で表現する
+///
+/// u64のナノ秒で表せるのは2554年まで。範囲外やUNIX EPOCHより前の時刻は書き込めない
+pub mod timestamp_nanos {
+    use std::time::SystemTime;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::value::TimeScale;
+
+    pub fn serialize<S>(date: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let nanos = TimeScale::Nanos
+            .from_system_time(*date)
+            .ok_or_else(|| serde::ser::Error::custom("systemtime is out of range of nanos"))?;
+        serializer.serialize_u64(nanos)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let nanos = u64::deserialize(deserializer)?;
+        TimeScale::Nanos
+            .to_system_time(nanos)
+            .ok_or_else(|| serde::de::Error::custom("failed to deserialize systemtime"))
+    }
+}
+
+/// `Option<&[u8]>` をbyte列として書き込む
+///
+/// serdeの `&[u8]` はシーケンスとして扱われるため、byte列として書き込むのに使う。
+/// Keyがあれば長さ0でも `Some(&[])` として読む。
+/// 借用するため `#[serde(borrow)]` を、Keyが無い場合にNoneとするため `#[serde(default)]` も合わせて付ける
+pub mod option_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(v: &Option<&[u8]>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match v {
+            Some(x) => serializer.serialize_bytes(x),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<&'de [u8]>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        <&'de [u8]>::deserialize(deserializer).map(Some)
+    }
+}
+
+/// `Option<u16>` を1Byteまたは2Byteの符号なし整数で読み書きする
+///
+/// 1Byteで定義されていたが、後の版で2Byteも許されるようになったitem向け。
+/// 書き込みは1Byteに収まれば1Byte、収まらなければ2Byteで行う。
+/// Keyが無い場合にNoneとするため `#[serde(default)]` も合わせて付ける
+pub mod option_u8_or_u16 {
+    use serde::{Deserializer, Serializer};
+
+    use super::BeUintVisitor;
+
+    pub fn serialize<S>(v: &Option<u16>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match v {
+            Some(x) => match u8::try_from(*x) {
+                Ok(x) => serializer.serialize_u8(x),
+                Err(_) => serializer.serialize_u16(*x),
+            },
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // 2Byteまでなのでu16に収まる
+        deserializer
+            .deserialize_bytes(BeUintVisitor { max: 2 })
+            .map(|x| x.map(|x| x as u16))
+    }
+}
+
+/// `Option<u32>` をitemのLengthに応じた幅の符号なし整数で読む
+///
+/// 仕様では2Byteだが4Byteで書き込む実装もあるitem向け。Lengthが1, 2, 4Byteのいずれでも読み込める。
+/// 書き込みは従来どおり4Byteで行う。Keyが無い場合にNoneとするため `#[serde(default)]` も合わせて付ける
+pub mod option_be_uint {
+    use serde::{Deserializer, Serializer};
+
+    use super::BeUintVisitor;
+
+    pub fn serialize<S>(v: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match v {
+            Some(x) => serializer.serialize_u32(*x),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // 4Byteまでなのでu32に収まる
+        deserializer
+            .deserialize_bytes(BeUintVisitor { max: 4 })
+            .map(|x| x.map(|x| x as u32))
+    }
+}
+
+/// `max` Byte以下の1, 2, 4, 8Byteの符号なしBigEndian整数を読むVisitor
+///
+/// 版や実装によって幅が異なるitem向け。
+/// 以前のエンコーダはNoneを長さ0で書き込んでいたため、長さ0はNoneとする
+struct BeUintVisitor {
+    max: usize,
+}
+
+impl<'de> serde::de::Visitor<'de> for BeUintVisitor {
+    type Value = Option<u64>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter,
+            "1 to {} bytes of big endian unsigned integer",
+            self.max
+        )
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match v.len() {
+            0 => Ok(None),
+            x @ (1 | 2 | 4 | 8) if x <= self.max => Ok(Some(BigEndian::read_uint(v, x))),
+            x => Err(E::invalid_length(x, &self)),
+        }
+    }
+}
+
+/// `N` Byte固定長のbyte列を読むVisitor
+struct FixedBytesVisitor<const N: usize>;
+
+impl<'de, const N: usize> serde::de::Visitor<'de> for FixedBytesVisitor<N> {
+    type Value = [u8; N];
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{} bytes", N)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        v.try_into().map_err(|_| E::invalid_length(v.len(), &self))
+    }
+}
+
+/// `std::net::Ipv4Addr` を4Byteで表現する
+///
+/// ```ignore
+/// #[serde(rename = "10", with = "klv::serde_helpers::ipv4")]
+/// addr: Ipv4Addr,
+/// ```
+pub mod ipv4 {
+    use std::net::Ipv4Addr;
+
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S>(addr: &Ipv4Addr, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&addr.octets())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Ipv4Addr, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_bytes(super::FixedBytesVisitor::<4>)
+            .map(Ipv4Addr::from)
+    }
+}
+
+/// `std::net::Ipv6Addr` を16Byteで表現する
+pub mod ipv6 {
+    use std::net::Ipv6Addr;
+
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S>(addr: &Ipv6Addr, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&addr.octets())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Ipv6Addr, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_bytes(super::FixedBytesVisitor::<16>)
+            .map(Ipv6Addr::from)
+    }
+}
+
+/// 6ByteのMACアドレス。そのまま6Byteのvalueとして読み書きする
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl serde::Serialize for MacAddr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for MacAddr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_bytes(FixedBytesVisitor::<6>)
+            .map(MacAddr)
+    }
+}
+
+/// `Option<&str>` を読む。Keyがあれば長さ0でも `Some("")` とする
+///
+/// 通常の `Option` は長さ0のitemをNoneとして読むため、空文字列を区別したいitem向け。
+/// 書き込みは通常の `&str` と同じなので `deserialize_with` で指定する
+///
+/// ```ignore
+/// #[serde(
+///     rename = "3",
+///     borrow,
+///     default,
+///     skip_serializing_if = "Option::is_none",
+///     deserialize_with = "klv::serde_helpers::option_str"
+/// )]
+/// mission_id: Option<&'a str>,
+/// ```
+pub fn option_str<'de, D>(deserializer: D) -> Result<Option<&'de str>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    <&str as serde::Deserialize>::deserialize(deserializer).map(Some)
+}
+
+/// 長さの上限付きで `Option<&str>` を読む
+///
+/// `option_str` と同様に、Keyがあれば長さ0でも `Some("")` とする
+///
+/// 可変長の文字列item向け。`N` Byteを超える場合はエラーにする。
+/// 書き込みは通常の `&str` と同じなので `deserialize_with` で指定する
+///
+/// ```ignore
+/// #[serde(
+///     rename = "59",
+///     borrow,
+///     default,
+///     skip_serializing_if = "Option::is_none",
+///     deserialize_with = "klv::serde_helpers::option_str_max::<_, 127>"
+/// )]
+/// platform_call_sign: Option<&'a str>,
+/// ```
+pub fn option_str_max<'de, D, const N: usize>(deserializer: D) -> Result<Option<&'de str>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = <&str as serde::Deserialize>::deserialize(deserializer)?;
+    if s.len() > N {
+        return Err(serde::de::Error::invalid_length(
+            s.len(),
+            &format!("string of {} bytes or less", N).as_str(),
+        ));
+    }
+    Ok(Some(s))
+}
+
+/// `std::time::Duration` をマイクロ秒(u64)で表現する
+pub mod duration_micros {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u64(duration.as_micros() as u64)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let micros = u64::deserialize(deserializer)?;
+        Ok(Duration::from_micros(micros))
+    }
+}
+
+/// `std::time::Duration` を秒(u64)とナノ秒(u32)の12Byteで表現する
+///
+/// ```ignore
+/// #[serde(rename = "10", with = "klv::serde_helpers::duration")]
+/// elapsed: Duration,
+/// ```
+pub mod duration {
+    use std::fmt;
+    use std::time::Duration;
+
+    use byteorder::{BigEndian, ByteOrder};
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    const LENGTH: usize = 12;
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut buf = [0; LENGTH];
+        BigEndian::write_u64(&mut buf[..8], duration.as_secs());
+        BigEndian::write_u32(&mut buf[8..], duration.subsec_nanos());
+        serializer.serialize_bytes(&buf)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(DurationVisitor)
+    }
+
+    struct DurationVisitor;
+
+    impl<'de> Visitor<'de> for DurationVisitor {
+        type Value = Duration;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "{} bytes of secs and nanos", LENGTH)
+        }
+
+        fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            if v.len() != LENGTH {
+                return Err(E::invalid_length(v.len(), &self));
+            }
+            let secs = BigEndian::read_u64(&v[..8]);
+            let nanos = BigEndian::read_u32(&v[8..]);
+            if nanos >= 1_000_000_000 {
+                return Err(E::custom(format!("nanos out of range {}", nanos)));
+            }
+            Ok(Duration::new(secs, nanos))
+        }
+    }
+}
+
+/// `SystemTime` をRFC 3339形式のASCII文字列で表現する
+///
+/// バイナリのマイクロ秒ではなく文字列で時刻を埋め込む相手向け。
+/// 秒未満は必要な桁数(ミリ/マイクロ/ナノ秒)で書き込む
+///
+/// ```ignore
+/// #[serde(rename = "2", with = "klv::serde_helpers::timestamp_rfc3339")]
+/// timestamp: SystemTime,
+/// ```
+#[cfg(feature = "chrono")]
+pub mod timestamp_rfc3339 {
+    use std::time::SystemTime;
+
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// RFC 3339の文字列をSystemTimeにする
+    pub fn decode(x: &[u8]) -> Option<SystemTime> {
+        let s = std::str::from_utf8(x).ok()?;
+        DateTime::parse_from_rfc3339(s).ok().map(SystemTime::from)
+    }
+
+    /// SystemTimeをUTCのRFC 3339文字列にする
+    pub fn encode(t: SystemTime) -> String {
+        DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    }
+
+    pub fn serialize<S>(date: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&encode(*date))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = <&str>::deserialize(deserializer)?;
+        decode(s.as_bytes()).ok_or_else(|| {
+            serde::de::Error::custom(format!("failed to parse rfc3339 timestamp {}", s))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::serde_helpers::MacAddr;
+    use crate::{from_bytes, to_bytes};
+
+    #[test]
+    fn test_net_addr() {
+        use std::net::{Ipv4Addr, Ipv6Addr};
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestAddr {
+            #[serde(rename = "10", with = "crate::serde_helpers::ipv4")]
+            v4: Ipv4Addr,
+            #[serde(rename = "11", with = "crate::serde_helpers::ipv6")]
+            v6: Ipv6Addr,
+            #[serde(rename = "12")]
+            mac: MacAddr,
+        }
+        let t = TestAddr {
+            v4: Ipv4Addr::new(192, 168, 0, 1),
+            v6: "fe80::1".parse().unwrap(),
+            mac: MacAddr([0x00, 0x1b, 0x21, 0xaa, 0xbb, 0xcc]),
+        };
+        let s = to_bytes(&t).unwrap();
+        assert_eq!(&s[17..23], &[10, 4, 192, 168, 0, 1]);
+        assert_eq!(&s[23..25], &[11, 16]);
+        assert_eq!(&s[25..41], &t.v6.octets());
+        assert_eq!(&s[41..], &[12, 6, 0x00, 0x1b, 0x21, 0xaa, 0xbb, 0xcc]);
+        assert_eq!(from_bytes::<TestAddr>(&s).unwrap(), t);
+
+        // Lengthが異なる
+        let mut s = s;
+        s[18] = 3;
+        s.remove(22);
+        s[16] -= 1;
+        assert!(from_bytes::<TestAddr>(&s).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_nanos() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestTimestamp {
+            #[serde(rename = "2", with = "crate::serde_helpers::timestamp_nanos")]
+            ts: SystemTime,
+        }
+        // 2009-06-17T16:53:05.099653123Z
+        let nanos = 1_245_257_585_099_653_123u64;
+        let mut content = vec![2, 8];
+        content.extend_from_slice(&nanos.to_be_bytes());
+        let s = crate::frame(b"TESTDATA00000000", &content);
+        let x = from_bytes::<TestTimestamp>(&s).unwrap();
+        assert_eq!(
+            x.ts,
+            SystemTime::UNIX_EPOCH + Duration::new(1_245_257_585, 99_653_123)
+        );
+        assert_eq!(to_bytes(&x).unwrap(), s);
+
+        let t = TestTimestamp {
+            ts: SystemTime::UNIX_EPOCH - Duration::from_secs(1),
+        };
+        assert!(to_bytes(&t).is_err());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_timestamp_rfc3339() {
+        use crate::serde_helpers::timestamp_rfc3339;
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct TestTimestamp {
+            #[serde(rename = "2", with = "crate::serde_helpers::timestamp_rfc3339")]
+            ts: SystemTime,
+        }
+        let expect = SystemTime::UNIX_EPOCH + Duration::from_micros(1_245_257_585_099_653);
+        let text = "2009-06-17T16:53:05.099653Z";
+        assert_eq!(timestamp_rfc3339::decode(text.as_bytes()), Some(expect));
+        assert_eq!(timestamp_rfc3339::encode(expect), text);
+        assert!(timestamp_rfc3339::decode(b"2009-06-17").is_none());
+
+        let t = TestTimestamp { ts: expect };
+        let s = to_bytes(&t).unwrap();
+        // Key, Lengthに続いて文字列がそのまま入る
+        assert_eq!(&s[17..19], &[2, text.len() as u8]);
+        assert_eq!(&s[19..], text.as_bytes());
+        assert_eq!(from_bytes::<TestTimestamp>(&s).unwrap(), t);
+    }
+}
@@ -0,0 +1,130 @@
+//! テスト用のユーティリティ
+
+use std::fmt::{self, Display};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::Error;
+use crate::{from_bytes, parse_length, to_bytes, KLVReader};
+
+/// `assert_roundtrip` で見つかった不一致
+#[derive(Debug)]
+pub enum RoundtripError {
+    // 元の値を書き込めなかった
+    Serialize(Error),
+    // 書き込んだbyte列を読み込めなかった
+    Deserialize(Error),
+    // 読み込んだ値が元の値と異なる
+    // 読み込んだ値を再度書き込み、元のbyte列とitem単位で比べて最初に異なるitemのKeyとoffsetを示す。
+    // offsetは元のbyte列でのKeyの位置で、元に無いitemであれば末尾になる。全itemが同じ場合はどちらもNone
+    Mismatch {
+        offset: Option<usize>,
+        tag: Option<u8>,
+    },
+}
+
+impl Display for RoundtripError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoundtripError::Serialize(e) => write!(f, "failed to serialize: {}", e),
+            RoundtripError::Deserialize(e) => write!(f, "failed to deserialize: {}", e),
+            RoundtripError::Mismatch {
+                offset: Some(offset),
+                tag,
+            } => write!(f, "roundtrip differs at offset {} tag {:?}", offset, tag),
+            RoundtripError::Mismatch { offset: None, .. } => {
+                f.write_str("roundtrip differs with same items")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RoundtripError {}
+
+/// `from_bytes(to_bytes(value)) == value` を確認する
+///
+/// 異なる場合は最初に異なるitemのKeyと位置を `RoundtripError::Mismatch` で返す
+pub fn assert_roundtrip<T>(value: &T) -> Result<(), RoundtripError>
+where
+    T: Serialize + DeserializeOwned + PartialEq,
+{
+    let buf = to_bytes(value).map_err(RoundtripError::Serialize)?;
+    let decoded = from_bytes::<T>(&buf).map_err(RoundtripError::Deserialize)?;
+    if &decoded == value {
+        return Ok(());
+    }
+    let rebuf = to_bytes(&decoded).map_err(RoundtripError::Serialize)?;
+    let (header_len, items) = items_of(&buf);
+    let (_, reitems) = items_of(&rebuf);
+    let diverged = (0..items.len().max(reitems.len())).find(|i| items.get(*i) != reitems.get(*i));
+    let offset = diverged.map(|i| items.get(i).map_or(buf.len(), |x| header_len + x.0));
+    let tag = diverged.and_then(|i| items.get(i).or_else(|| reitems.get(i)).map(|x| x.1));
+    Err(RoundtripError::Mismatch { offset, tag })
+}
+
+// 各itemの(Keyのoffset, Key, value)。offsetはcontentの先頭から
+type Items<'a> = Vec<(usize, u8, &'a [u8])>;
+
+fn items_of(buf: &[u8]) -> (usize, Items<'_>) {
+    let mut items = vec![];
+    let header_len = match buf.get(16..).map(parse_length) {
+        Some(Ok((length_len, _))) => 16 + length_len,
+        _ => return (buf.len(), items),
+    };
+    KLVReader::from_bytes(&buf[header_len..]).for_each_with_offset(|offset, key, x| {
+        if let Ok(x) = x {
+            items.push((offset, key, x.key_and_content().1));
+        }
+    });
+    (header_len, items)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::testutil::{assert_roundtrip, RoundtripError};
+
+    #[test]
+    fn test_assert_roundtrip() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test {
+            #[serde(rename = "10")]
+            a: u8,
+            #[serde(rename = "11")]
+            b: Option<String>,
+        }
+        assert!(assert_roundtrip(&Test {
+            a: 1,
+            b: Some("x".to_string())
+        })
+        .is_ok());
+        assert!(assert_roundtrip(&Test { a: 1, b: None }).is_ok());
+
+        // 0は書き込まれず、読み込むと1になる
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Broken {
+            #[serde(rename = "10")]
+            a: u8,
+            #[serde(rename = "11", skip_serializing_if = "is_zero", default = "one")]
+            b: u8,
+        }
+        fn is_zero(x: &u8) -> bool {
+            *x == 0
+        }
+        fn one() -> u8 {
+            1
+        }
+        assert!(assert_roundtrip(&Broken { a: 1, b: 2 }).is_ok());
+        match assert_roundtrip(&Broken { a: 1, b: 0 }) {
+            Err(RoundtripError::Mismatch { offset, tag }) => {
+                // 元のbyte列に無い11が増えている
+                assert_eq!(offset, Some(20));
+                assert_eq!(tag, Some(11));
+            }
+            x => unreachable!("{:?}", x),
+        }
+    }
+}
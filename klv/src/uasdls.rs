@@ -2,17 +2,42 @@
 //! the Unmanned Air System (UAS) Datalink Local Set (LS)
 //! reference: MISB ST 0601.8
 
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::str::FromStr;
 use std::time::SystemTime;
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::{Error, Result};
+use crate::value::{decode_int, TimestampWindow};
+use crate::{bcc16, peek_header, KLVReader};
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename = "\x06\x0e\x2b\x34\x02\x0b\x01\x01\x0e\x01\x03\x01\x01\x00\x00\x00")]
 pub struct UASDatalinkLS<'a> {
     #[serde(rename = "1")]
     pub checksum: u16,
-    #[serde(rename = "2", with = "timestamp_micro")]
+    #[serde(rename = "2", with = "crate::serde_helpers::timestamp_micros")]
     pub timestamp: SystemTime,
+    /// Mission ID. Max 127 bytes.
+    #[serde(
+        rename = "3",
+        borrow,
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::serde_helpers::option_str_max::<_, 127>"
+    )]
+    pub mission_id: Option<&'a str>,
+    /// Platform Tail Number. Max 127 bytes.
+    #[serde(
+        rename = "4",
+        borrow,
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::serde_helpers::option_str_max::<_, 127>"
+    )]
+    pub platform_tail_number: Option<&'a str>,
     /// Relative between longitudinal axis and True North measured in the horizontal plane.
     /// Map 0..(2^16-1) to 0..360.
     /// Resolution: ~5.5 milli degrees.
@@ -32,9 +57,32 @@ pub struct UASDatalinkLS<'a> {
     /// Res: ~1525 micro deg.
     #[serde(rename = "7")]
     pub platform_roll_angle: i16,
-    #[serde(rename = "11", skip_serializing_if = "Option::is_none")]
+    /// Platform Designation. Max 127 bytes.
+    #[serde(
+        rename = "10",
+        borrow,
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::serde_helpers::option_str_max::<_, 127>"
+    )]
+    pub platform_designation: Option<&'a str>,
+    /// Image Source Sensor. Max 127 bytes.
+    #[serde(
+        rename = "11",
+        borrow,
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::serde_helpers::option_str_max::<_, 127>"
+    )]
     pub image_source_sensor: Option<&'a str>,
-    #[serde(rename = "12", skip_serializing_if = "Option::is_none")]
+    /// Image Coordinate System. Max 127 bytes. See [`CoordinateSystem`].
+    #[serde(
+        rename = "12",
+        borrow,
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::serde_helpers::option_str_max::<_, 127>"
+    )]
     pub image_coordinate_sensor: Option<&'a str>,
 
     #[serde(rename = "13", skip_serializing_if = "Option::is_none")]
@@ -42,6 +90,8 @@ pub struct UASDatalinkLS<'a> {
     #[serde(rename = "14", skip_serializing_if = "Option::is_none")]
     pub sensor_longtude: Option<i32>,
 
+    /// Altitude of sensor as measured from Mean Sea Level (MSL).
+    /// Map 0..(2^16-1) to -900..19000 meters. See [`altitude_to_meters`].
     #[serde(rename = "15", skip_serializing_if = "Option::is_none")]
     pub sensor_true_altitude: Option<u16>,
     #[serde(rename = "16", skip_serializing_if = "Option::is_none")]
@@ -56,10 +106,16 @@ pub struct UASDatalinkLS<'a> {
     #[serde(rename = "20", skip_serializing_if = "Option::is_none")]
     pub sensor_relative_roll_angle: Option<i32>,
 
+    /// Slant Range. Map 0..(2^32-1) to 0..5000000 meters. See [`range_to_meters`].
     #[serde(rename = "21", skip_serializing_if = "Option::is_none")]
     pub slant_range: Option<u32>,
-    // ST 0601.8の仕様書ではではu16だがテストデータでは4バイトだったのでu32とする
-    #[serde(rename = "22", skip_serializing_if = "Option::is_none")]
+    // ST 0601.8の仕様書ではではu16だがテストデータでは4バイトだったので、Lengthに応じて読みu32で保持する
+    #[serde(
+        rename = "22",
+        skip_serializing_if = "Option::is_none",
+        with = "crate::serde_helpers::option_be_uint",
+        default
+    )]
     pub target_width: Option<u32>,
 
     #[serde(rename = "23", skip_serializing_if = "Option::is_none")]
@@ -69,6 +125,26 @@ pub struct UASDatalinkLS<'a> {
     #[serde(rename = "25", skip_serializing_if = "Option::is_none")]
     pub frame_center_elevation: Option<u16>,
 
+    /// Offset Corner Latitude/Longitude Point 1..4.
+    /// Offset from the frame center. Map -(2^15-1)..(2^15-1) to +/-0.075 degrees.
+    /// Use -(2^15) as "error" indicator. See [`to_corner_degrees`].
+    #[serde(rename = "26", skip_serializing_if = "Option::is_none")]
+    pub offset_corner_latitude_point_1: Option<i16>,
+    #[serde(rename = "27", skip_serializing_if = "Option::is_none")]
+    pub offset_corner_longitude_point_1: Option<i16>,
+    #[serde(rename = "28", skip_serializing_if = "Option::is_none")]
+    pub offset_corner_latitude_point_2: Option<i16>,
+    #[serde(rename = "29", skip_serializing_if = "Option::is_none")]
+    pub offset_corner_longitude_point_2: Option<i16>,
+    #[serde(rename = "30", skip_serializing_if = "Option::is_none")]
+    pub offset_corner_latitude_point_3: Option<i16>,
+    #[serde(rename = "31", skip_serializing_if = "Option::is_none")]
+    pub offset_corner_longitude_point_3: Option<i16>,
+    #[serde(rename = "32", skip_serializing_if = "Option::is_none")]
+    pub offset_corner_latitude_point_4: Option<i16>,
+    #[serde(rename = "33", skip_serializing_if = "Option::is_none")]
+    pub offset_corner_longitude_point_4: Option<i16>,
+
     #[serde(rename = "40", skip_serializing_if = "Option::is_none")]
     pub target_location_latitude: Option<i32>,
     #[serde(rename = "41", skip_serializing_if = "Option::is_none")]
@@ -76,12 +152,58 @@ pub struct UASDatalinkLS<'a> {
     #[serde(rename = "42", skip_serializing_if = "Option::is_none")]
     pub target_location_elecation: Option<u16>,
 
-    #[serde(rename = "56", skip_serializing_if = "Option::is_none")]
-    pub plafform_ground_speed: Option<u8>,
+    /// Bitfield of miscellaneous flags. See [`GenericFlags`].
+    #[serde(rename = "47", skip_serializing_if = "Option::is_none")]
+    pub generic_flag_data: Option<u8>,
+
+    /// Platform Ground Speed (m/s). 1 byte, or 2 bytes in later revisions.
+    #[serde(
+        rename = "56",
+        skip_serializing_if = "Option::is_none",
+        with = "crate::serde_helpers::option_u8_or_u16",
+        default
+    )]
+    pub plafform_ground_speed: Option<u16>,
+    /// Ground Range. Map 0..(2^32-1) to 0..5000000 meters. See [`range_to_meters`].
     #[serde(rename = "57", skip_serializing_if = "Option::is_none")]
     pub ground_range: Option<u32>,
+    /// Platform Call Sign. Max 127 bytes.
+    #[serde(
+        rename = "59",
+        borrow,
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::serde_helpers::option_str_max::<_, 127>"
+    )]
+    pub platform_call_sign: Option<&'a str>,
+    /// Sensor Field of View Name. See [`FovName`].
+    #[serde(rename = "63", skip_serializing_if = "Option::is_none")]
+    pub sensor_fov_name: Option<u8>,
     #[serde(rename = "65")]
     pub ls_version_number: u8,
+
+    /// Sensor Ellipsoid Height as measured from the WGS84 ellipsoid.
+    /// Map 0..(2^16-1) to -900..19000 meters.
+    #[serde(rename = "75", skip_serializing_if = "Option::is_none")]
+    pub sensor_ellipsoid_height: Option<u16>,
+    /// Alternate Platform Ellipsoid Height as measured from the WGS84 ellipsoid.
+    /// Map 0..(2^16-1) to -900..19000 meters.
+    #[serde(rename = "76", skip_serializing_if = "Option::is_none")]
+    pub alternate_platform_ellipsoid_height: Option<u16>,
+    /// Frame Center Height above the WGS84 ellipsoid.
+    /// Map 0..(2^16-1) to -900..19000 meters.
+    #[serde(rename = "78", skip_serializing_if = "Option::is_none")]
+    pub frame_center_height_above_ellipsoid: Option<u16>,
+
+    /// Motion Imagery Identification System Core Identifier (MISB ST 1204)
+    #[serde(
+        rename = "94",
+        borrow,
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::serde_helpers::option_bytes"
+    )]
+    pub miis_core_identifier: Option<&'a [u8]>,
 }
 
 impl<'a> Default for UASDatalinkLS<'a> {
@@ -89,9 +211,12 @@ impl<'a> Default for UASDatalinkLS<'a> {
         Self {
             checksum: Default::default(),
             timestamp: SystemTime::UNIX_EPOCH,
+            mission_id: Default::default(),
+            platform_tail_number: Default::default(),
             platform_heading_angle: Default::default(),
             platform_pitch_angle: Default::default(),
             platform_roll_angle: Default::default(),
+            platform_designation: Default::default(),
             image_source_sensor: Default::default(),
             image_coordinate_sensor: Default::default(),
             sensor_latitude: Default::default(),
@@ -107,95 +232,726 @@ impl<'a> Default for UASDatalinkLS<'a> {
             frame_center_latitude: Default::default(),
             frame_center_longitude: Default::default(),
             frame_center_elevation: Default::default(),
+            offset_corner_latitude_point_1: Default::default(),
+            offset_corner_longitude_point_1: Default::default(),
+            offset_corner_latitude_point_2: Default::default(),
+            offset_corner_longitude_point_2: Default::default(),
+            offset_corner_latitude_point_3: Default::default(),
+            offset_corner_longitude_point_3: Default::default(),
+            offset_corner_latitude_point_4: Default::default(),
+            offset_corner_longitude_point_4: Default::default(),
             target_location_latitude: Default::default(),
             target_location_longitude: Default::default(),
             target_location_elecation: Default::default(),
+            generic_flag_data: Default::default(),
             plafform_ground_speed: Default::default(),
             ground_range: Default::default(),
+            platform_call_sign: Default::default(),
+            sensor_fov_name: Default::default(),
             ls_version_number: Default::default(),
+            sensor_ellipsoid_height: Default::default(),
+            alternate_platform_ellipsoid_height: Default::default(),
+            frame_center_height_above_ellipsoid: Default::default(),
+            miis_core_identifier: Default::default(),
         }
     }
 }
 
-mod timestamp_micro {
-    use serde::{Deserialize, Deserializer, Serializer};
-    use std::time::{Duration, SystemTime};
+impl<'a> UASDatalinkLS<'a> {
+    /// `ls_version_number` に対応する規格の版
+    pub fn ls_revision(&self) -> &'static str {
+        uas_ls_revision(self.ls_version_number)
+    }
+
+    /// Timestampが `window` の範囲内か検査する。範囲外は `Error::Message` を返す
+    ///
+    /// 壊れたpacketの検出用。`from_bytes` では検査しないので必要に応じて呼ぶ
+    pub fn validate_timestamp(&self, window: &TimestampWindow) -> Result<()> {
+        if window.contains(self.timestamp) {
+            Ok(())
+        } else {
+            Err(Error::Message(format!(
+                "timestamp is out of range {:?}",
+                self.timestamp
+            )))
+        }
+    }
+
+    /// Generic Flag Dataを各bitのフラグとして読む
+    pub fn generic_flags(&self) -> Option<GenericFlags> {
+        self.generic_flag_data.map(GenericFlags)
+    }
+
+    /// Sensor Field of View Nameを列挙値にする。未定義の値はエラー
+    pub fn fov_name(&self) -> Option<Result<FovName>> {
+        self.sensor_fov_name.map(FovName::try_from)
+    }
+
+    /// Image Coordinate Systemを座標系にする
+    pub fn image_coordinate_system(&self) -> Option<CoordinateSystem> {
+        self.image_coordinate_sensor
+            .map(|x| x.parse().unwrap_or_else(|e: Infallible| match e {}))
+    }
+
+    /// 4隅のOffset Cornerをframe centerに加えた緯度経度(度)にする
+    ///
+    /// いずれかの隅が欠けている場合はNone
+    pub fn corner_points(&self, frame_center: (f64, f64)) -> Option<[(f64, f64); 4]> {
+        let offsets = [
+            (
+                self.offset_corner_latitude_point_1?,
+                self.offset_corner_longitude_point_1?,
+            ),
+            (
+                self.offset_corner_latitude_point_2?,
+                self.offset_corner_longitude_point_2?,
+            ),
+            (
+                self.offset_corner_latitude_point_3?,
+                self.offset_corner_longitude_point_3?,
+            ),
+            (
+                self.offset_corner_latitude_point_4?,
+                self.offset_corner_longitude_point_4?,
+            ),
+        ];
+        Some(offsets.map(|x| to_corner_degrees(frame_center, x)))
+    }
+
+    /// MIIS Core Identifierのbyte列を2Byteごとに`:`で区切った16進文字列にする
+    ///
+    /// ログ表示用の単純な16進ダンプで、ST 1204で定められたテキスト表記(UUID形式)ではない
+    pub fn miis_core_identifier_hex_dump(&self) -> Option<String> {
+        self.miis_core_identifier.map(|x| {
+            x.chunks(2)
+                .map(|c| c.iter().map(|b| format!("{:02X}", b)).collect::<String>())
+                .collect::<Vec<_>>()
+                .join(":")
+        })
+    }
+}
+
+/// 高度系のTag(15, 25, 42, 75, 76, 78)の値をメートルに変換する
+///
+/// 0..(2^16-1)を-900..19000メートルに対応させる
+pub fn altitude_to_meters(v: u16) -> f64 {
+    v as f64 * 19900.0 / u16::MAX as f64 - 900.0
+}
+
+/// 距離系のTag(21, 57)の値をメートルに変換する
+///
+/// 0..(2^32-1)を0..5000000メートルに対応させる
+pub fn range_to_meters(v: u32) -> f64 {
+    v as f64 * 5_000_000.0 / u32::MAX as f64
+}
+
+/// Offset Corner(26..33)の(緯度, 経度)のoffsetをframe centerに加えて度にする
+///
+/// offsetは-(2^15-1)..(2^15-1)を+/-0.075度に対応させる
+pub fn to_corner_degrees(frame_center: (f64, f64), offset: (i16, i16)) -> (f64, f64) {
+    let scale = 0.075 / i16::MAX as f64;
+    (
+        frame_center.0 + offset.0 as f64 * scale,
+        frame_center.1 + offset.1 as f64 * scale,
+    )
+}
+
+/// Tagの名称。ST 0601の表記に従う
+pub fn tag_name(tag: u8) -> Option<&'static str> {
+    let name = match tag {
+        1 => "Checksum",
+        2 => "Precision Time Stamp",
+        3 => "Mission ID",
+        4 => "Platform Tail Number",
+        5 => "Platform Heading Angle",
+        6 => "Platform Pitch Angle",
+        7 => "Platform Roll Angle",
+        10 => "Platform Designation",
+        11 => "Image Source Sensor",
+        12 => "Image Coordinate System",
+        13 => "Sensor Latitude",
+        14 => "Sensor Longitude",
+        15 => "Sensor True Altitude",
+        16 => "Sensor Horizontal Field of View",
+        17 => "Sensor Vertical Field of View",
+        18 => "Sensor Relative Azimuth Angle",
+        19 => "Sensor Relative Elevation Angle",
+        20 => "Sensor Relative Roll Angle",
+        21 => "Slant Range",
+        22 => "Target Width",
+        23 => "Frame Center Latitude",
+        24 => "Frame Center Longitude",
+        25 => "Frame Center Elevation",
+        26 => "Offset Corner Latitude Point 1",
+        27 => "Offset Corner Longitude Point 1",
+        28 => "Offset Corner Latitude Point 2",
+        29 => "Offset Corner Longitude Point 2",
+        30 => "Offset Corner Latitude Point 3",
+        31 => "Offset Corner Longitude Point 3",
+        32 => "Offset Corner Latitude Point 4",
+        33 => "Offset Corner Longitude Point 4",
+        40 => "Target Location Latitude",
+        41 => "Target Location Longitude",
+        42 => "Target Location Elevation",
+        47 => "Generic Flag Data",
+        56 => "Platform Ground Speed",
+        57 => "Ground Range",
+        59 => "Platform Call Sign",
+        63 => "Sensor Field of View Name",
+        65 => "UAS Datalink LS Version Number",
+        75 => "Sensor Ellipsoid Height",
+        76 => "Alternate Platform Ellipsoid Height",
+        78 => "Frame Center Height Above Ellipsoid",
+        94 => "MIIS Core Identifier",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// `scaled_value` で得られる値の単位
+///
+/// 単位の無いTagや未対応のTagはNone
+pub fn unit(tag: u8) -> Option<&'static str> {
+    let unit = match tag {
+        5..=7 | 13 | 14 | 16..=20 | 23 | 24 | 26..=33 | 40 | 41 => "deg",
+        15 | 21 | 22 | 25 | 42 | 57 | 75 | 76 | 78 => "m",
+        56 => "m/s",
+        _ => return None,
+    };
+    Some(unit)
+}
+
+// 値から物理量への対応
+enum Scale {
+    // 符号なし整数の0..(2^n-1)をmin..maxに対応させる
+    Unsigned(f64, f64),
+    // Unsignedと同じだが、itemの幅によらずnを固定する
+    UnsignedBits(f64, f64, i32),
+    // 符号付き整数の-(2^(n-1)-1)..(2^(n-1)-1)を+/-xに対応させる。-(2^(n-1))はエラー値
+    Signed(f64),
+    // 値をそのまま使う
+    Raw,
+}
+
+fn scale_of(tag: u8) -> Option<Scale> {
+    let scale = match tag {
+        2 | 47 | 56 | 65 => Scale::Raw,
+        5 | 18 | 20 => Scale::Unsigned(0.0, 360.0),
+        6 => Scale::Signed(20.0),
+        7 => Scale::Signed(50.0),
+        13 | 23 | 40 => Scale::Signed(90.0),
+        14 | 19 | 24 | 41 => Scale::Signed(180.0),
+        15 | 25 | 42 | 75 | 76 | 78 => Scale::Unsigned(-900.0, 19000.0),
+        16 | 17 => Scale::Unsigned(0.0, 180.0),
+        21 | 57 => Scale::Unsigned(0.0, 5_000_000.0),
+        // 4Byteで書き込まれていてもST 0601のuint16の値を持つ
+        22 => Scale::UnsignedBits(0.0, 10_000.0, 16),
+        26..=33 => Scale::Signed(0.075),
+        _ => return None,
+    };
+    Some(scale)
+}
+
+/// itemの値をST 0601の対応で物理量(度, メートルなど)にする
+///
+/// 対応が分からないTag、整数として読めない長さ、エラー値の場合はNone
+pub fn scaled_value(tag: u8, raw: &[u8]) -> Option<f64> {
+    let bits = raw.len() as i32 * 8;
+    match scale_of(tag)? {
+        Scale::Raw => decode_int(raw, false).map(|x| x as f64),
+        Scale::Unsigned(min, max) => {
+            let v = decode_int(raw, false)?;
+            Some(min + v as f64 * (max - min) / (2f64.powi(bits) - 1.0))
+        }
+        Scale::UnsignedBits(min, max, bits) => {
+            let v = decode_int(raw, false)?;
+            Some(min + v as f64 * (max - min) / (2f64.powi(bits) - 1.0))
+        }
+        Scale::Signed(x) => {
+            let v = decode_int(raw, true)?;
+            let half = 2f64.powi(bits - 1);
+            if v as f64 == -half {
+                return None;
+            }
+            Some(v as f64 * x / (half - 1.0))
+        }
+    }
+}
+
+/// contentの数値のTagを順に `scaled_value` で物理量にして(Tag, 値)を返す
+///
+/// 文字列、Timestamp(Tag 2)、対応が分からないTagは含めない。
+/// エラー値や整数として読めない長さの場合は値をNoneとする。不正なitemに到達した場合はそこで終わる
+pub fn scaled_values(content: &[u8]) -> impl Iterator<Item = (u8, Option<f64>)> + '_ {
+    KLVReader::from_bytes(content)
+        .map_while(|x| x.ok())
+        .map(|x| x.key_and_content())
+        .filter(|(tag, _)| *tag != 2 && scale_of(*tag).is_some())
+        .map(|(tag, raw)| (tag, scaled_value(tag, raw)))
+}
+
+// 文字列のTag
+fn is_string_tag(tag: u8) -> bool {
+    matches!(tag, 3 | 4 | 10 | 11 | 12 | 59)
+}
+
+/// `to_csv_row` に対応するヘッダ行。名称が分からないTagは番号にする
+pub fn csv_header(columns: &[u8]) -> String {
+    columns
+        .iter()
+        .map(|x| match tag_name(*x) {
+            Some(name) => name.to_string(),
+            None => x.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// contentから `columns` のTagを順に取り出し、物理量にしてCSVの1行にする
+///
+/// 文字列のTagはそのまま書き込み、無いTagや物理量にできないTagは空にする。
+/// 同じTagが複数ある場合は最初のものを使う
+pub fn to_csv_row(content: &[u8], columns: &[u8]) -> String {
+    let items = KLVReader::from_bytes(content).group_by_tag();
+    columns
+        .iter()
+        .map(|tag| {
+            let Some(raw) = items.get(tag).and_then(|x| x.first()) else {
+                return String::new();
+            };
+            if is_string_tag(*tag) {
+                return csv_escape(&String::from_utf8_lossy(raw));
+            }
+            scaled_value(*tag, raw)
+                .map(|x| x.to_string())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// contentの各itemをTag, 名称, Length, 値の16進, 物理量の列に揃えた表にする
+///
+/// 名称が分からないTagは `unknown`、物理量にできない値は `-` とする。
+/// 不正なitemに到達した場合はそこまでの行の後にエラーを示す行を加える
+pub fn dump_table(content: &[u8]) -> String {
+    let mut rows = vec![[
+        "tag".to_string(),
+        "name".to_string(),
+        "len".to_string(),
+        "hex".to_string(),
+        "value".to_string(),
+    ]];
+    let mut error = None;
+    for x in KLVReader::from_bytes(content) {
+        let x = match x {
+            Ok(x) => x,
+            Err(e) => {
+                error = Some(e);
+                continue;
+            }
+        };
+        let (tag, raw) = x.key_and_content();
+        let hex = raw.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let value = if is_string_tag(tag) {
+            String::from_utf8_lossy(raw).into_owned()
+        } else {
+            match (scaled_value(tag, raw), unit(tag)) {
+                (Some(v), Some(unit)) => format!("{} {}", v, unit),
+                (Some(v), None) => v.to_string(),
+                (None, _) => "-".to_string(),
+            }
+        };
+        rows.push([
+            tag.to_string(),
+            tag_name(tag).unwrap_or("unknown").to_string(),
+            raw.len().to_string(),
+            hex,
+            value,
+        ]);
+    }
+
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (w, x) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(x.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for [tag, name, len, hex, value] in &rows {
+        let line = format!(
+            "{:>tw$}  {:<nw$}  {:>lw$}  {:<hw$}  {}",
+            tag,
+            name,
+            len,
+            hex,
+            value,
+            tw = widths[0],
+            nw = widths[1],
+            lw = widths[2],
+            hw = widths[3],
+        );
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    if let Some(e) = error {
+        table.push_str(&format!("error: {}\n", e));
+    }
+    table
+}
+
+/// Generic Flag Data(47)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericFlags(pub u8);
+
+impl GenericFlags {
+    fn bit(&self, n: u8) -> bool {
+        self.0 & (1 << n) != 0
+    }
+    /// 0: Laser off, 1: Laser on
+    pub fn laser_range(&self) -> bool {
+        self.bit(0)
+    }
+    /// 0: Auto-Track off, 1: Auto-Track on
+    pub fn auto_track(&self) -> bool {
+        self.bit(1)
+    }
+    /// 0: White Hot, 1: Black Hot
+    pub fn ir_polarity_black_hot(&self) -> bool {
+        self.bit(2)
+    }
+    /// 0: No icing detected, 1: Icing detected
+    pub fn icing_detected(&self) -> bool {
+        self.bit(3)
+    }
+    /// 0: Calculated, 1: Measured
+    pub fn slant_range_measured(&self) -> bool {
+        self.bit(4)
+    }
+    /// 0: Image Valid, 1: Image Invalid
+    pub fn image_invalid(&self) -> bool {
+        self.bit(5)
+    }
+}
+
+/// Image Coordinate System(12)の座標系
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    /// Geodetic WGS84
+    Wgs84,
+    /// Geocentric WGS84
+    GeocentricWgs84,
+    Utm,
+    Nad83,
+    /// 対応していない文字列。元の文字列を保持する
+    Other(String),
+}
+
+impl FromStr for CoordinateSystem {
+    type Err = Infallible;
 
-    pub fn serialize<S>(date: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let micros = date
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-        serializer.serialize_u64(micros as u64)
+    /// 大文字小文字と前後の空白を無視して比べる
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let x = match s.trim().to_ascii_uppercase().as_str() {
+            "GEODETIC WGS84" | "GEODETIC WGS-84" | "WGS84" | "WGS-84" => CoordinateSystem::Wgs84,
+            "GEOCENTRIC WGS84" | "GEOCENTRIC WGS-84" => CoordinateSystem::GeocentricWgs84,
+            "UTM" => CoordinateSystem::Utm,
+            "NAD83" | "NAD-83" | "GEODETIC NAD83" => CoordinateSystem::Nad83,
+            _ => CoordinateSystem::Other(s.to_string()),
+        };
+        Ok(x)
+    }
+}
+
+/// Sensor Field of View Name(63)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FovName {
+    Ultranarrow,
+    Narrow,
+    Medium,
+    Wide,
+    Ultrawide,
+    NarrowMedium,
+    Ultranarrow2x,
+    Ultranarrow4x,
+    ContinuousZoom,
+}
+
+impl TryFrom<u8> for FovName {
+    type Error = Error;
+
+    fn try_from(v: u8) -> Result<Self> {
+        let x = match v {
+            0 => FovName::Ultranarrow,
+            1 => FovName::Narrow,
+            2 => FovName::Medium,
+            3 => FovName::Wide,
+            4 => FovName::Ultrawide,
+            5 => FovName::NarrowMedium,
+            6 => FovName::Ultranarrow2x,
+            7 => FovName::Ultranarrow4x,
+            8 => FovName::ContinuousZoom,
+            x => {
+                return Err(Error::Message(format!(
+                    "unknown sensor field of view name {}",
+                    x
+                )))
+            }
+        };
+        Ok(x)
+    }
+}
+
+/// LS Version Number(65)を対応するMISB規格の版に変換する
+///
+/// 1はEngineering Guideline時代のEG 0601.1を、2以降はST 0601.Nを示す
+pub fn uas_ls_revision(v: u8) -> &'static str {
+    match v {
+        0 => "ST 0601",
+        1 => "EG 0601.1",
+        2 => "ST 0601.2",
+        3 => "ST 0601.3",
+        4 => "ST 0601.4",
+        5 => "ST 0601.5",
+        6 => "ST 0601.6",
+        7 => "ST 0601.7",
+        8 => "ST 0601.8",
+        9 => "ST 0601.9",
+        10 => "ST 0601.10",
+        11 => "ST 0601.11",
+        12 => "ST 0601.12",
+        13 => "ST 0601.13",
+        14 => "ST 0601.14",
+        15 => "ST 0601.15",
+        16 => "ST 0601.16",
+        17 => "ST 0601.17",
+        18 => "ST 0601.18",
+        19 => "ST 0601.19",
+        _ => "unknown",
+    }
+}
+
+/// セットのPrecision Time Stamp(2)を書き換え、checksum(1)を計算し直す
+///
+/// デコードとエンコードをせずにその場で書き換える。Tag 2が無いか8Byteでない場合、
+/// checksumが無いか2Byteでない場合はエラー
+pub fn retime(framed: &mut [u8], new_ts: SystemTime) -> Result<()> {
+    let (_, content_len, header_len) = peek_header(framed)?;
+    let content = framed
+        .get(header_len..header_len + content_len)
+        .ok_or(Error::ContentLenght)?;
+    let item = |tag: u8, len: usize| -> Result<usize> {
+        let x = KLVReader::from_bytes(content)
+            .map_while(|x| x.ok())
+            .find(|x| x.key == tag)
+            .ok_or_else(|| Error::Key(format!("tag {} is not found", tag)))?;
+        if x.length != len {
+            return Err(Error::TypeLength(format!(
+                "key: {} expect {} got {}",
+                tag, len, x.length
+            )));
+        }
+        Ok(header_len + x.position)
+    };
+    let ts_at = item(2, 8)?;
+    let checksum_at = item(1, 2)?;
+    let micros = new_ts
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|e| Error::Encode(e.to_string()))?
+        .as_micros() as u64;
+    framed[ts_at..ts_at + 8].copy_from_slice(&micros.to_be_bytes());
+    let checksum = bcc16(&framed[..checksum_at]);
+    framed[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+    Ok(())
+}
+
+/// LS Version Number(65)を先に読み、contentを読むKLVReaderと組にして返す
+///
+/// 版によって解釈を変える場合に使う。Tag 65が無いか1Byteでない場合はNone
+pub fn decode_with_version(content: &[u8]) -> (Option<u8>, KLVReader<'_>) {
+    let version = KLVReader::from_bytes(content)
+        .map_while(|x| x.ok())
+        .find(|x| x.key == 65)
+        .and_then(|x| match x.key_and_content().1 {
+            [v] => Some(*v),
+            _ => None,
+        });
+    (version, KLVReader::from_bytes(content))
+}
+
+/// contentのTagの組み合わせを検査する
+///
+/// ```
+/// use klv::uasdls::UasSetValidator;
+///
+/// // Sensor Latitude(13)とSensor Longitude(14)
+/// let validator = UasSetValidator::new().require_pair(13, 14);
+/// assert!(validator.validate(&[13, 1, 0x00, 14, 1, 0x00]).is_ok());
+/// assert!(validator.validate(&[13, 1, 0x00]).is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct UasSetValidator {
+    pairs: Vec<(u8, u8)>,
+}
+
+impl UasSetValidator {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let micros = u64::deserialize(deserializer)?;
-        SystemTime::UNIX_EPOCH
-            .checked_add(Duration::from_micros(micros))
-            .ok_or_else(|| serde::de::Error::custom("failed to deserialize systemtime"))
+    /// aとbは両方あるか、両方ないかのどちらかでなければならない
+    pub fn require_pair(mut self, a: u8, b: u8) -> Self {
+        self.pairs.push((a, b));
+        self
+    }
+
+    /// 規則に反するTagの組を見つけた場合は `Error::Key` を返す
+    pub fn validate(&self, content: &[u8]) -> Result<()> {
+        let tags = KLVReader::from_bytes(content)
+            .map(|x| x.map(|x| x.key))
+            .collect::<Result<BTreeSet<_>>>()?;
+        for (a, b) in &self.pairs {
+            match (tags.contains(a), tags.contains(b)) {
+                (true, false) => {
+                    return Err(Error::Key(format!(
+                        "tag {} is present without tag {}",
+                        a, b
+                    )))
+                }
+                (false, true) => {
+                    return Err(Error::Key(format!(
+                        "tag {} is present without tag {}",
+                        b, a
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{de::from_bytes, se::to_bytes, uasdls::UASDatalinkLS};
+    use crate::{
+        bcc16,
+        de::{
+            checksum_range, content_contains_tag, from_bytes, spawn_decode, tag_coverage, KLVReader,
+        },
+        error::Error,
+        frame,
+        se::{to_bytes, Serializer},
+        uasdls::{
+            altitude_to_meters, csv_header, decode_with_version, dump_table, range_to_meters,
+            retime, scaled_value, scaled_values, tag_name, to_corner_degrees, to_csv_row,
+            uas_ls_revision, unit, CoordinateSystem, FovName, GenericFlags, UASDatalinkLS,
+            UasSetValidator,
+        },
+        value::TimestampWindow,
+    };
     use chrono::{DateTime, Utc};
+    use std::collections::BTreeSet;
     use std::time::{Duration, SystemTime};
 
+    #[rustfmt::skip]
+    const SAMPLE: &[u8] = &[
+        0x06, 0x0e, 0x2b, 0x34, 0x02, 0x0b, 0x01, 0x01, 0x0e, 0x01, 0x03, 0x01, 0x01, 0x00, 0x00,0x00,
+        129, 0x91,
+        2, 8, 0, 0x4, 0x6c, 0x8e, 0x20, 0x03, 0x83, 0x85,
+        65, 1, 1,
+        5, 2, 0x3d, 0x3b,
+        6, 2, 0x15, 0x80,
+        7, 2, 0x01, 0x52,
+        11, 3, 0x45, 0x4f, 0x4e,
+        12, 14, 0x47, 0x65, 0x6f, 0x64, 0x65, 0x74, 0x69, 0x63, 0x20, 0x57, 0x47, 0x53, 0x38, 0x34,
+        13, 4, 0x4d, 0xc4, 0xdc, 0xbb,
+        14, 4, 0xb1, 0xa8, 0x6c, 0xfe,
+        15, 2, 0x1f, 0x4a,
+        16, 2, 0x00, 0x85,
+        17, 2, 0x00, 0x4b,
+        18, 4, 0x20, 0xc8, 0xd2, 0x7d,
+        19, 4, 0xfc, 0xdd, 0x02, 0xd8,
+        20, 4, 0xfe, 0xb8, 0xcb, 0x61,
+        21, 4, 0x00, 0x8f, 0x3e, 0x61,
+        22, 4, 0x00, 0x00, 0x01, 0xc9,
+        23, 4, 0x4d, 0xdd, 0x8c, 0x2a,
+        24, 4, 0xb1, 0xbe, 0x9e, 0xf4,
+        25, 2, 0x0b, 0x85,
+        40, 4, 0x4d, 0xdd, 0x8c, 0x2a,
+        41, 4, 0xb1, 0xbe, 0x9e, 0xf4,
+        42, 2, 0x0b, 0x85,
+        56, 1, 0x2e,
+        57, 4, 0x00, 0x8d, 0xd4, 0x29,
+        1, 2, 0x1c, 0x5f
+    ];
+
     #[test]
     fn test_uas_datalink_ls() {
-        #[rustfmt::skip]
-        let buf = vec![
-            0x06, 0x0e, 0x2b, 0x34, 0x02, 0x0b, 0x01, 0x01, 0x0e, 0x01, 0x03, 0x01, 0x01, 0x00, 0x00,0x00,
-            129, 0x91,
-            2, 8, 0, 0x4, 0x6c, 0x8e, 0x20, 0x03, 0x83, 0x85,
-            65, 1, 1,
-            5, 2, 0x3d, 0x3b,
-            6, 2, 0x15, 0x80,
-            7, 2, 0x01, 0x52,
-            11, 3, 0x45, 0x4f, 0x4e,
-            12, 14, 0x47, 0x65, 0x6f, 0x64, 0x65, 0x74, 0x69, 0x63, 0x20, 0x57, 0x47, 0x53, 0x38, 0x34,
-            13, 4, 0x4d, 0xc4, 0xdc, 0xbb,
-            14, 4, 0xb1, 0xa8, 0x6c, 0xfe,
-            15, 2, 0x1f, 0x4a,
-            16, 2, 0x00, 0x85,
-            17, 2, 0x00, 0x4b,
-            18, 4, 0x20, 0xc8, 0xd2, 0x7d,
-            19, 4, 0xfc, 0xdd, 0x02, 0xd8,
-            20, 4, 0xfe, 0xb8, 0xcb, 0x61,
-            21, 4, 0x00, 0x8f, 0x3e, 0x61,
-            22, 4, 0x00, 0x00, 0x01, 0xc9,
-            23, 4, 0x4d, 0xdd, 0x8c, 0x2a,
-            24, 4, 0xb1, 0xbe, 0x9e, 0xf4,
-            25, 2, 0x0b, 0x85,
-            40, 4, 0x4d, 0xdd, 0x8c, 0x2a,
-            41, 4, 0xb1, 0xbe, 0x9e, 0xf4,
-            42, 2, 0x0b, 0x85,
-            56, 1, 0x2e,
-            57, 4, 0x00, 0x8d, 0xd4, 0x29,
-            1, 2, 0x1c, 0x5f
-            ];
+        let buf = SAMPLE;
 
-        let x = from_bytes::<UASDatalinkLS>(&buf).unwrap();
+        let x = from_bytes::<UASDatalinkLS>(buf).unwrap();
         let datetime: DateTime<Utc> = x.timestamp.into();
         assert_eq!(
             DateTime::parse_from_rfc3339("2009-06-17T16:53:05.099653+00:00").unwrap(),
             datetime
         );
         assert_eq!(x.ls_version_number, 1);
+        assert_eq!(x.ls_revision(), "EG 0601.1");
         assert_eq!(x.platform_heading_angle, 15675);
         assert_eq!(x.sensor_latitude, Some(1304747195));
         assert_eq!(x.image_source_sensor, Some("EON"));
         assert_eq!(x.image_coordinate_sensor, Some("Geodetic WGS84"));
     }
 
+    #[test]
+    fn test_truncated_timestamp() {
+        // 上位1byteを省略した7ByteのTimestamp
+        let mut content = vec![2, 7];
+        content.extend_from_slice(&SAMPLE[21..]);
+        let s = frame(&SAMPLE[..16].try_into().unwrap(), &content);
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(x, from_bytes::<UASDatalinkLS>(SAMPLE).unwrap());
+
+        // 5Byteは読まない
+        let mut content = vec![2, 5];
+        content.extend_from_slice(&SAMPLE[23..]);
+        let s = frame(&SAMPLE[..16].try_into().unwrap(), &content);
+        assert!(from_bytes::<UASDatalinkLS>(&s).is_err());
+    }
+
+    #[test]
+    fn test_validate_timestamp() {
+        let x = from_bytes::<UASDatalinkLS>(SAMPLE).unwrap();
+        assert!(x.validate_timestamp(&TimestampWindow::default()).is_ok());
+
+        // 2010-01-01以降のみ許す
+        let window = TimestampWindow {
+            not_before: SystemTime::UNIX_EPOCH + Duration::from_secs(1_262_304_000),
+            ..Default::default()
+        };
+        match x.validate_timestamp(&window) {
+            Err(Error::Message(_)) => {}
+            _ => unreachable!(),
+        }
+
+        // 壊れた値でも読み込みは成功する
+        let t = UASDatalinkLS {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(32_503_680_000),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert!(x.validate_timestamp(&TimestampWindow::default()).is_err());
+    }
+
     #[test]
     fn test_serialize() {
         let ts = SystemTime::UNIX_EPOCH
@@ -214,4 +970,531 @@ mod tests {
         let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
         assert_eq!(t, x);
     }
+
+    #[test]
+    fn test_filter_tags() {
+        let tags = KLVReader::from_bytes(&SAMPLE[18..])
+            .filter_tags(40..=42)
+            .map(|x| x.unwrap().key)
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec![40, 41, 42]);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_to_json_hex() {
+        let x = crate::json::to_json_hex(&SAMPLE[18..]);
+        assert_eq!(x["65"], "01");
+        assert_eq!(x["13"], "4dc4dcbb");
+        assert_eq!(x["11"], "454f4e");
+        assert!(x.get("_error").is_none());
+
+        // 末尾が途中で切れていてもpanicしない
+        let x = crate::json::to_json_hex(&SAMPLE[18..SAMPLE.len() - 1]);
+        assert_eq!(x["57"], "008dd429");
+        assert!(x.get("1").is_none());
+        assert!(x.get("_error").is_some());
+    }
+
+    #[test]
+    fn test_uas_ls_revision() {
+        assert_eq!(uas_ls_revision(1), "EG 0601.1");
+        assert_eq!(uas_ls_revision(8), "ST 0601.8");
+        assert_eq!(uas_ls_revision(255), "unknown");
+    }
+
+    #[test]
+    fn test_decode_with_version() {
+        let (version, reader) = decode_with_version(&SAMPLE[18..]);
+        assert_eq!(version, Some(1));
+        assert_eq!(reader.count(), 26);
+
+        let (version, _) = decode_with_version(&[2, 1, 0x00]);
+        assert_eq!(version, None);
+    }
+
+    #[test]
+    fn test_miis_core_identifier() {
+        #[rustfmt::skip]
+        let id = [
+            0x01, 0x70, 0xf5, 0x92, 0xf0, 0x23, 0x73, 0x36, 0x4a, 0xf8, 0xaa, 0x91, 0x62, 0xc0, 0x0f, 0x2e,
+            0xb2, 0xda, 0x16, 0xb7, 0x41, 0x34, 0xc5, 0x55, 0x4d, 0x7c, 0xb9, 0xa8, 0x5f, 0xc3, 0x28, 0x6e,
+            0x03,
+        ];
+        let t = UASDatalinkLS {
+            miis_core_identifier: Some(&id),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(x.miis_core_identifier, Some(&id[..]));
+        assert_eq!(
+            x.miis_core_identifier_hex_dump().unwrap(),
+            "0170:F592:F023:7336:4AF8:AA91:62C0:0F2E:B2DA:16B7:4134:C555:4D7C:B9A8:5FC3:286E:03"
+        );
+        assert!(UASDatalinkLS::default()
+            .miis_core_identifier_hex_dump()
+            .is_none());
+    }
+
+    #[test]
+    fn test_content_contains_tag() {
+        assert!(content_contains_tag(&SAMPLE[18..], 65));
+        assert!(content_contains_tag(&SAMPLE[18..], 1));
+        assert!(!content_contains_tag(&SAMPLE[18..], 94));
+    }
+
+    #[test]
+    fn test_tag_coverage() {
+        let coverage = tag_coverage(&SAMPLE[18..]);
+        assert_eq!(
+            coverage.iter().copied().collect::<Vec<_>>(),
+            vec![
+                1, 2, 5, 6, 7, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 40, 41,
+                42, 56, 57, 65
+            ]
+        );
+        // 必須Keyのうち欠けているもの
+        let required = [1, 2, 3, 65].into_iter().collect::<BTreeSet<_>>();
+        assert_eq!(required.difference(&coverage).collect::<Vec<_>>(), vec![&3]);
+    }
+
+    #[test]
+    fn test_generic_flags() {
+        let t = UASDatalinkLS {
+            generic_flag_data: Some(0b0001_0011),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        let flags = x.generic_flags().unwrap();
+        assert_eq!(flags, GenericFlags(0b0001_0011));
+        assert!(flags.laser_range());
+        assert!(flags.auto_track());
+        assert!(!flags.ir_polarity_black_hot());
+        assert!(!flags.icing_detected());
+        assert!(flags.slant_range_measured());
+        assert!(!flags.image_invalid());
+    }
+
+    #[test]
+    fn test_image_coordinate_system() {
+        let x = from_bytes::<UASDatalinkLS>(SAMPLE).unwrap();
+        assert_eq!(x.image_coordinate_system(), Some(CoordinateSystem::Wgs84));
+
+        assert_eq!(
+            "geocentric wgs84".parse(),
+            Ok(CoordinateSystem::GeocentricWgs84)
+        );
+        assert_eq!(" NAD83".parse(), Ok(CoordinateSystem::Nad83));
+        assert_eq!(
+            "Local Grid".parse(),
+            Ok(CoordinateSystem::Other("Local Grid".to_string()))
+        );
+        assert!(UASDatalinkLS::default().image_coordinate_system().is_none());
+    }
+
+    #[test]
+    fn test_fov_name() {
+        let t = UASDatalinkLS {
+            sensor_fov_name: Some(3),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(x.fov_name().unwrap().unwrap(), FovName::Wide);
+        assert_eq!(FovName::try_from(8).unwrap(), FovName::ContinuousZoom);
+        assert!(FovName::try_from(9).is_err());
+        assert!(UASDatalinkLS::default().fov_name().is_none());
+    }
+
+    #[test]
+    fn test_range_to_meters() {
+        assert_eq!(range_to_meters(0), 0.0);
+        assert_eq!(range_to_meters(u32::MAX), 5_000_000.0);
+
+        let x = from_bytes::<UASDatalinkLS>(SAMPLE).unwrap();
+        let slant = range_to_meters(x.slant_range.unwrap());
+        assert!((slant - 10928.62).abs() < 0.01, "{}", slant);
+        let ground = range_to_meters(x.ground_range.unwrap());
+        assert!((ground - 10820.67).abs() < 0.01, "{}", ground);
+        assert_eq!(scaled_value(21, &[0x00, 0x8f, 0x3e, 0x61]), Some(slant));
+        assert_eq!(unit(21), Some("m"));
+        assert_eq!(unit(57), Some("m"));
+        assert_eq!(unit(65), None);
+    }
+
+    #[test]
+    fn test_altitude_to_meters() {
+        assert_eq!(altitude_to_meters(0), -900.0);
+        assert_eq!(altitude_to_meters(u16::MAX), 19000.0);
+        // サンプルのSensor True Altitude
+        assert!((altitude_to_meters(0x1f4a) - 1532.27).abs() < 0.01);
+
+        let t = UASDatalinkLS {
+            sensor_ellipsoid_height: Some(0),
+            alternate_platform_ellipsoid_height: Some(u16::MAX),
+            frame_center_height_above_ellipsoid: Some(0x1f4a),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(t, x);
+        // Tag 76はMSLではなくWGS84楕円体からの高さ
+        assert_eq!(tag_name(76), Some("Alternate Platform Ellipsoid Height"));
+    }
+
+    #[test]
+    fn test_checksum_range() {
+        assert_eq!(SAMPLE.len(), 163);
+        // 末尾の 1, 2, 0x1c, 0x5f のLengthまで
+        assert_eq!(checksum_range(SAMPLE, 1), Some(0..161));
+        assert_eq!(checksum_range(SAMPLE, 94), None);
+        assert_eq!(checksum_range(&SAMPLE[..100], 1), None);
+        assert_eq!(bcc16(&SAMPLE[..161]), 0x1c5f);
+    }
+
+    #[test]
+    fn test_retime() {
+        let mut buf = SAMPLE.to_vec();
+        let ts = SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        retime(&mut buf, ts).unwrap();
+        let x = from_bytes::<UASDatalinkLS>(&buf).unwrap();
+        assert_eq!(x.timestamp, ts);
+        let range = checksum_range(&buf, 1).unwrap();
+        assert_eq!(bcc16(&buf[range]), x.checksum);
+        assert_ne!(x.checksum, 0x1c5f);
+        // Timestamp以外は変わらない
+        assert_eq!(&buf[..20], &SAMPLE[..20]);
+        assert_eq!(&buf[28..161], &SAMPLE[28..161]);
+
+        // Tag 2が無い
+        let mut buf = frame(&SAMPLE[..16].try_into().unwrap(), &[65, 1, 1, 1, 2, 0, 0]);
+        assert!(matches!(retime(&mut buf, ts), Err(Error::Key(_))));
+        // Tag 2が8Byteでない
+        let mut buf = frame(
+            &SAMPLE[..16].try_into().unwrap(),
+            &[2, 4, 0, 0, 0, 1, 1, 2, 0, 0],
+        );
+        assert!(matches!(retime(&mut buf, ts), Err(Error::TypeLength(_))));
+    }
+
+    #[test]
+    fn test_ground_speed() {
+        let x = from_bytes::<UASDatalinkLS>(SAMPLE).unwrap();
+        assert_eq!(x.plafform_ground_speed, Some(0x2e));
+
+        // 1Byteに収まる値は1Byte、収まらない値は2Byteで書き込む
+        for (speed, len) in [(140u16, 1), (300, 2)] {
+            let t = UASDatalinkLS {
+                plafform_ground_speed: Some(speed),
+                ..Default::default()
+            };
+            let s = to_bytes(&t).unwrap();
+            let item = KLVReader::from_bytes(&s[17..])
+                .map_while(|x| x.ok())
+                .find(|x| x.key == 56)
+                .unwrap();
+            assert_eq!(item.length, len);
+            let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+            assert_eq!(x.plafform_ground_speed, Some(speed));
+        }
+
+        // 3Byteは読めない
+        let content = [
+            2, 8, 0, 0, 0, 0, 0, 0, 0, 0, 56, 3, 0, 1, 0, 65, 1, 1, 1, 2, 0, 0,
+        ];
+        let s = frame(&SAMPLE[..16].try_into().unwrap(), &content);
+        assert!(from_bytes::<UASDatalinkLS>(&s).is_err());
+    }
+
+    #[test]
+    fn test_target_width() {
+        // テストデータは4Byte
+        let x = from_bytes::<UASDatalinkLS>(SAMPLE).unwrap();
+        assert_eq!(x.target_width, Some(0x1c9));
+
+        let t = UASDatalinkLS {
+            target_width: Some(0x1c9),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        assert!(s.windows(6).any(|x| x == [22, 4, 0x00, 0x00, 0x01, 0xc9]));
+        assert_eq!(from_bytes::<UASDatalinkLS>(&s).unwrap(), t);
+
+        // 仕様どおりの2Byte
+        let s = to_bytes(&UASDatalinkLS::default()).unwrap();
+        let mut content = s[17..].to_vec();
+        content.extend_from_slice(&[22, 2, 0x01, 0xc9]);
+        let s = frame(s[..16].try_into().unwrap(), &content);
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(x.target_width, Some(0x1c9));
+
+        // 3Byteは読めない
+        let mut content = to_bytes(&UASDatalinkLS::default()).unwrap()[17..].to_vec();
+        content.extend_from_slice(&[22, 3, 0x00, 0x01, 0xc9]);
+        let s = frame(s[..16].try_into().unwrap(), &content);
+        assert!(from_bytes::<UASDatalinkLS>(&s).is_err());
+
+        // 以前のエンコーダが書き込んだ長さ0のNone
+        let mut content = to_bytes(&UASDatalinkLS::default()).unwrap()[17..].to_vec();
+        content.extend_from_slice(&[22, 0]);
+        let s = frame(s[..16].try_into().unwrap(), &content);
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(x.target_width, None);
+    }
+
+    #[test]
+    fn test_corner_points() {
+        let center = (35.0, 139.0);
+        assert_eq!(to_corner_degrees(center, (0, 0)), center);
+        let (lat, lon) = to_corner_degrees(center, (i16::MAX, -i16::MAX));
+        assert!((lat - 35.075).abs() < 1e-9);
+        assert!((lon - 138.925).abs() < 1e-9);
+
+        let t = UASDatalinkLS {
+            offset_corner_latitude_point_1: Some(-5000),
+            offset_corner_longitude_point_1: Some(16384),
+            offset_corner_latitude_point_2: Some(5000),
+            offset_corner_longitude_point_2: Some(16384),
+            offset_corner_latitude_point_3: Some(5000),
+            offset_corner_longitude_point_3: Some(-16384),
+            offset_corner_latitude_point_4: Some(-5000),
+            offset_corner_longitude_point_4: Some(-16384),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        assert!(s.windows(4).any(|x| x == [26, 2, 0xec, 0x78]));
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(t, x);
+
+        let corners = x.corner_points(center).unwrap();
+        let (lat, lon) = corners[0];
+        assert!((lat - (35.0 - 5000.0 * 0.075 / 32767.0)).abs() < 1e-9);
+        assert!((lon - (139.0 + 16384.0 * 0.075 / 32767.0)).abs() < 1e-9);
+        assert!(UASDatalinkLS::default().corner_points(center).is_none());
+    }
+
+    #[test]
+    fn test_variable_strings() {
+        let t = UASDatalinkLS {
+            mission_id: Some("MISSION01"),
+            platform_tail_number: Some("AF-101"),
+            platform_designation: Some("MQ1-B"),
+            platform_call_sign: Some("TOP_GUN"),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        let x = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(t, x);
+
+        // 上限を超える
+        let long = "A".repeat(128);
+        let t = UASDatalinkLS {
+            platform_call_sign: Some(&long),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        assert!(from_bytes::<UASDatalinkLS>(&s).is_err());
+
+        // Lengthが残りのbyte数を超える
+        let t = UASDatalinkLS {
+            ls_version_number: 1,
+            mission_id: Some("MISSION01"),
+            ..Default::default()
+        };
+        let mut s = to_bytes(&t).unwrap();
+        let at = s.windows(2).position(|x| x == [3, 9]).unwrap();
+        s[at + 1] = 0x7f;
+        assert!(from_bytes::<UASDatalinkLS>(&s).is_err());
+    }
+
+    #[test]
+    fn test_uas_set_validator() {
+        let validator = UasSetValidator::new()
+            .require_pair(13, 14)
+            .require_pair(23, 24);
+        assert!(validator.validate(&SAMPLE[18..]).is_ok());
+
+        // Sensor Latitudeだけある
+        let t = UASDatalinkLS {
+            sensor_latitude: Some(1304747195),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        match validator.validate(&s[17..]) {
+            Err(Error::Key(msg)) => assert_eq!(msg, "tag 13 is present without tag 14"),
+            x => unreachable!("{:?}", x),
+        }
+        // 両方ない
+        let s = to_bytes(&UASDatalinkLS::default()).unwrap();
+        assert!(validator.validate(&s[17..]).is_ok());
+        // 不正なitem
+        assert!(validator.validate(&SAMPLE[18..SAMPLE.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_scaled_value() {
+        assert_eq!(scaled_value(5, &[0xff, 0xff]), Some(360.0));
+        assert_eq!(scaled_value(6, &[0x7f, 0xff]), Some(20.0));
+        assert_eq!(scaled_value(6, &[0x80, 0x01]), Some(-20.0));
+        // エラー値
+        assert_eq!(scaled_value(6, &[0x80, 0x00]), None);
+        assert_eq!(scaled_value(15, &[0x00, 0x00]), Some(-900.0));
+        assert_eq!(scaled_value(65, &[0x01]), Some(1.0));
+        assert_eq!(scaled_value(11, b"EON"), None);
+        assert_eq!(scaled_value(200, &[0x01]), None);
+
+        // Target Widthは幅によらずuint16として対応させる
+        let width = 0x1c9 as f64 * 10_000.0 / 65535.0;
+        assert_eq!(scaled_value(22, &[0x01, 0xc9]), Some(width));
+        assert_eq!(scaled_value(22, &[0x00, 0x00, 0x01, 0xc9]), Some(width));
+        let x = from_bytes::<UASDatalinkLS>(SAMPLE).unwrap();
+        let raw = x.target_width.unwrap().to_be_bytes();
+        let width = scaled_value(22, &raw).unwrap();
+        assert!((width - 69.74).abs() < 0.01, "{}", width);
+        assert!(scaled_values(&SAMPLE[18..]).any(|x| x == (22, Some(width))));
+    }
+
+    #[test]
+    fn test_spawn_decode() {
+        let content = &SAMPLE[18..];
+        let rx = spawn_decode(content.to_vec());
+        let items = rx.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let expected = KLVReader::from_bytes(content)
+            .map(|x| {
+                let (key, value) = x.unwrap().key_and_content();
+                (key, value.to_vec())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 26);
+        assert_eq!(items, expected);
+
+        // 不正なitemはエラーとして届く
+        let rx = spawn_decode(vec![10, 1, 0x7f, 11, 5, 0x01]);
+        assert_eq!(rx.recv().unwrap().unwrap(), (10, vec![0x7f]));
+        assert!(matches!(rx.recv().unwrap(), Err(Error::ContentLenght)));
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_scaled_values() {
+        let values = scaled_values(&SAMPLE[18..]).collect::<Vec<_>>();
+        let heading = 0x3d3b as f64 * 360.0 / 65535.0;
+        let latitude = 0x4dc4dcbb as f64 * 90.0 / 2147483647.0;
+        assert!(values.contains(&(5, Some(heading))));
+        assert!(values.contains(&(13, Some(latitude))));
+        assert!(values.contains(&(65, Some(1.0))));
+        // 文字列、Timestamp、checksumは含まない
+        assert!(values
+            .iter()
+            .all(|(tag, _)| !matches!(tag, 1 | 2 | 3 | 4 | 10 | 11 | 12 | 59 | 94)));
+
+        // エラー値と不正なitem
+        let values = scaled_values(&[6, 2, 0x80, 0x00, 200, 1, 0x01, 5, 2, 0xff, 0xff, 7, 5]);
+        assert_eq!(
+            values.collect::<Vec<_>>(),
+            vec![(6, None), (5, Some(360.0))]
+        );
+    }
+
+    #[test]
+    fn test_to_csv_row() {
+        let columns = [5, 13, 11, 94, 65, 200];
+        assert_eq!(
+            csv_header(&columns),
+            "Platform Heading Angle,Sensor Latitude,Image Source Sensor,MIIS Core Identifier,UAS Datalink LS Version Number,200"
+        );
+        let heading = 0x3d3b as f64 * 360.0 / 65535.0;
+        let latitude = 0x4dc4dcbb as f64 * 90.0 / 2147483647.0;
+        assert_eq!(
+            to_csv_row(&SAMPLE[18..], &columns),
+            format!("{},{},EON,,1,", heading, latitude)
+        );
+        assert!((heading - 86.1067).abs() < 0.0001);
+        assert!((latitude - 54.6813).abs() < 0.0001);
+
+        // 区切り文字を含む文字列
+        let t = UASDatalinkLS {
+            mission_id: Some("a,\"b\""),
+            ..Default::default()
+        };
+        let s = to_bytes(&t).unwrap();
+        assert_eq!(to_csv_row(&s[17..], &[3]), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_dump_table() {
+        let table = dump_table(&SAMPLE[18..]);
+        let lines = table.lines().collect::<Vec<_>>();
+        // ヘッダと26item
+        assert_eq!(lines.len(), 27);
+        assert!(lines[0].starts_with("tag  name"));
+        let heading = 0x3d3b as f64 * 360.0 / 65535.0;
+        assert_eq!(
+            lines[3],
+            format!(
+                "  5  Platform Heading Angle             2  3d3b{}{} deg",
+                " ".repeat(26),
+                heading
+            )
+        );
+        assert!(lines[6].ends_with("454f4e                        EON"));
+        assert!(lines[26].starts_with("  1  Checksum "));
+        assert!(lines[26].ends_with("1c5f                          -"));
+        // 列が揃っている
+        let at = lines[0].find("hex").unwrap();
+        assert!(lines.iter().all(|x| x[..at].ends_with("  ")));
+
+        // 不明なTagと不正なitem
+        let table = dump_table(&[200, 1, 0x01, 10, 5, 0x01]);
+        assert_eq!(
+            table,
+            "tag  name     len  hex  value\n200  unknown    1  01   -\nerror: unexpected end of input or less\n"
+        );
+    }
+
+    #[test]
+    fn test_reencode() {
+        // 読み込んだchecksumのままstrictでも書き込める
+        let mut x = from_bytes::<UASDatalinkLS>(SAMPLE).unwrap();
+        x.platform_heading_angle = 0x1234;
+        let s = Serializer::new()
+            .sort_tags(true)
+            .embed_checksum(true)
+            .strict(true)
+            .to_bytes(&x)
+            .unwrap();
+        let y = from_bytes::<UASDatalinkLS>(&s).unwrap();
+        assert_eq!(bcc16(&s[checksum_range(&s, 1).unwrap()]), y.checksum);
+        assert_eq!(y.platform_heading_angle, 0x1234);
+        assert_eq!(
+            UASDatalinkLS { checksum: 0, ..y },
+            UASDatalinkLS { checksum: 0, ..x }
+        );
+    }
+
+    #[test]
+    fn test_labeled() {
+        let items = KLVReader::from_bytes(&SAMPLE[18..])
+            .labeled(tag_name)
+            .take(4)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            vec![
+                (
+                    Some("Precision Time Stamp"),
+                    &[0x00, 0x04, 0x6c, 0x8e, 0x20, 0x03, 0x83, 0x85][..]
+                ),
+                (Some("UAS Datalink LS Version Number"), &[0x01][..]),
+                (Some("Platform Heading Angle"), &[0x3d, 0x3b][..]),
+                (Some("Platform Pitch Angle"), &[0x15, 0x80][..]),
+            ]
+        );
+        let items = KLVReader::from_bytes(&[200, 1, 0x01])
+            .labeled(tag_name)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![(None, &[0x01][..])]);
+    }
 }
@@ -0,0 +1,212 @@
+//! itemのvalueを型に依らず読むための補助
+
+use std::time::{Duration, SystemTime};
+
+use byteorder::{BigEndian, ByteOrder};
+
+/// UNIX EPOCHからの経過時間を整数で表す際の単位
+///
+/// Tag 2(Timestamp)はMicrosだが、独自のLocal Setでは異なる単位を使うことがある
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeScale {
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl TimeScale {
+    /// 6-8ByteのBigEndian整数をこの単位でSystemTimeに変換する
+    ///
+    /// 上位byteを省略して6, 7Byteで書き込むエンコーダ向けに、8Byteに満たない分は上位を0とみなす
+    pub fn decode(&self, x: &[u8]) -> Option<SystemTime> {
+        if !(6..=8).contains(&x.len()) {
+            return None;
+        }
+        self.to_system_time(BigEndian::read_uint(x, x.len()))
+    }
+
+    pub fn to_system_time(&self, v: u64) -> Option<SystemTime> {
+        let d = match self {
+            TimeScale::Millis => Duration::from_millis(v),
+            TimeScale::Micros => Duration::from_micros(v),
+            TimeScale::Nanos => Duration::from_nanos(v),
+        };
+        SystemTime::UNIX_EPOCH.checked_add(d)
+    }
+
+    /// UNIX EPOCHより前やu64に収まらない場合はNone
+    pub fn from_system_time(&self, t: SystemTime) -> Option<u64> {
+        let d = t.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        let v = match self {
+            TimeScale::Millis => d.as_millis(),
+            TimeScale::Micros => d.as_micros(),
+            TimeScale::Nanos => d.as_nanos(),
+        };
+        u64::try_from(v).ok()
+    }
+}
+
+/// Timestampとして妥当とみなす範囲
+///
+/// bit反転などで壊れた値が遠い未来の時刻として読めてしまうのを検出するために使う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampWindow {
+    /// これより前の時刻は不正とする
+    pub not_before: SystemTime,
+    /// 現在時刻からこれ以上先の時刻は不正とする
+    pub max_ahead: Duration,
+}
+
+impl Default for TimestampWindow {
+    /// 1990-01-01から現在時刻の1年後まで
+    fn default() -> Self {
+        Self {
+            not_before: SystemTime::UNIX_EPOCH + Duration::from_secs(631_152_000),
+            max_ahead: Duration::from_secs(365 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl TimestampWindow {
+    pub fn contains(&self, t: SystemTime) -> bool {
+        match SystemTime::now().checked_add(self.max_ahead) {
+            Some(not_after) => t >= self.not_before && t <= not_after,
+            // SystemTimeで表せないほど先であれば上限なし
+            None => t >= self.not_before,
+        }
+    }
+}
+
+/// 1, 2, 4, 8ByteのBigEndian整数をByte数に応じて読む
+///
+/// 型の分からないitemを汎用的に表示する用途向け。`signed` であれば符号拡張する。
+/// それ以外のByte数や、符号なし8Byteでi64に収まらない場合はNone
+pub fn decode_int(x: &[u8], signed: bool) -> Option<i64> {
+    let v = match (x.len(), signed) {
+        (1, true) => x[0] as i8 as i64,
+        (1, false) => x[0] as i64,
+        (2, true) => BigEndian::read_i16(x) as i64,
+        (2, false) => BigEndian::read_u16(x) as i64,
+        (4, true) => BigEndian::read_i32(x) as i64,
+        (4, false) => BigEndian::read_u32(x) as i64,
+        (8, true) => BigEndian::read_i64(x),
+        (8, false) => i64::try_from(BigEndian::read_u64(x)).ok()?,
+        _ => return None,
+    };
+    Some(v)
+}
+
+/// 4, 8ByteのBigEndian IEEE 754浮動小数点数をByte数に応じて読む
+///
+/// `decode_int` の浮動小数点数版。それ以外のByte数はNone
+pub fn decode_float(x: &[u8]) -> Option<f64> {
+    let v = match x.len() {
+        4 => BigEndian::read_f32(x) as f64,
+        8 => BigEndian::read_f64(x),
+        _ => return None,
+    };
+    Some(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use serde::Deserialize;
+
+    use super::{decode_float, decode_int, TimeScale, TimestampWindow};
+    use crate::from_bytes;
+
+    #[test]
+    fn test_time_scale() {
+        let buf = 1_245_257_585_099_653u64.to_be_bytes();
+        let micros = TimeScale::Micros.decode(&buf).unwrap();
+        let millis = TimeScale::Millis.decode(&buf).unwrap();
+        let nanos = TimeScale::Nanos.decode(&buf).unwrap();
+        assert_eq!(
+            micros.duration_since(SystemTime::UNIX_EPOCH).unwrap(),
+            Duration::from_micros(1_245_257_585_099_653)
+        );
+        assert_eq!(
+            millis.duration_since(SystemTime::UNIX_EPOCH).unwrap(),
+            Duration::from_millis(1_245_257_585_099_653)
+        );
+        assert_eq!(
+            nanos.duration_since(SystemTime::UNIX_EPOCH).unwrap(),
+            Duration::from_nanos(1_245_257_585_099_653)
+        );
+        assert_eq!(
+            TimeScale::Micros.from_system_time(micros),
+            Some(1_245_257_585_099_653)
+        );
+
+        // 上位byteを省略した7Byte, 6Byte
+        assert_eq!(TimeScale::Micros.decode(&buf[1..]), Some(micros));
+        assert_eq!(
+            TimeScale::Micros.decode(&buf[2..]),
+            TimeScale::Micros.to_system_time(0x6c8e_2003_8385)
+        );
+        assert!(TimeScale::Micros.decode(&buf[3..]).is_none());
+        assert!(TimeScale::Micros.decode(&[0; 9]).is_none());
+    }
+
+    #[test]
+    fn test_decode_int() {
+        assert_eq!(decode_int(&[0xff], true), Some(-1));
+        assert_eq!(decode_int(&[0xff], false), Some(255));
+        assert_eq!(decode_int(&[0x80, 0x00], true), Some(-32768));
+        assert_eq!(decode_int(&[0x80, 0x00], false), Some(32768));
+        assert_eq!(decode_int(&[0xff, 0xff, 0xff, 0xfe], true), Some(-2));
+        assert_eq!(
+            decode_int(&[0xff, 0xff, 0xff, 0xfe], false),
+            Some(4294967294)
+        );
+        assert_eq!(decode_int(&[0xff; 8], true), Some(-1));
+        assert_eq!(decode_int(&[0xff; 8], false), None);
+        assert_eq!(decode_int(&[0x01, 0x02, 0x03], false), None);
+    }
+
+    #[test]
+    fn test_decode_float() {
+        assert_eq!(decode_float(&1.5f32.to_be_bytes()), Some(1.5));
+        assert_eq!(decode_float(&(-0.25f64).to_be_bytes()), Some(-0.25));
+        assert_eq!(decode_float(&[0x01, 0x02]), None);
+
+        // 自前のセットのitemとして読む
+        #[derive(Debug, Deserialize)]
+        #[serde(rename = "TESTDATA00000000")]
+        struct Test {
+            #[serde(rename = "10")]
+            f32: f32,
+            #[serde(rename = "11")]
+            f64: f64,
+        }
+        let mut buf = b"TESTDATA00000000".to_vec();
+        buf.extend_from_slice(&[16, 10, 4]);
+        buf.extend_from_slice(&3.25f32.to_be_bytes());
+        buf.extend_from_slice(&[11, 8]);
+        buf.extend_from_slice(&1e-3f64.to_be_bytes());
+        let x = from_bytes::<Test>(&buf).unwrap();
+        assert_eq!((x.f32, x.f64), (3.25, 1e-3));
+        let content = &buf[17..];
+        assert_eq!(decode_float(&content[2..6]), Some(3.25));
+        assert_eq!(decode_float(&content[8..]), Some(1e-3));
+    }
+
+    #[test]
+    fn test_timestamp_window() {
+        let window = TimestampWindow::default();
+        assert!(!window.contains(SystemTime::UNIX_EPOCH));
+        assert!(window.contains(SystemTime::now()));
+        // 3000年
+        assert!(!window.contains(SystemTime::UNIX_EPOCH + Duration::from_secs(32_503_680_000)));
+
+        // 上限なし
+        let window = TimestampWindow {
+            max_ahead: Duration::MAX,
+            ..Default::default()
+        };
+        assert!(window.contains(SystemTime::UNIX_EPOCH + Duration::from_secs(32_503_680_000)));
+        assert!(!window.contains(SystemTime::UNIX_EPOCH));
+    }
+}
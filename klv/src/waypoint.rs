@@ -0,0 +1,123 @@
+//! MISB ST 0601 Tag 141 Waypoint List
+//!
+//! 各WaypointはBER LengthとVariable Length Packの繰り返しで、Packは
+//! Waypoint ID (BER-OID), Prosecution Order (int16), Waypoint Info (BER-OID),
+//! 緯度 (IMAPB -90..90, 4Byte), 経度 (IMAPB -180..180, 4Byte), 高度 (IMAPB -900..9000, 3Byte) の順に並ぶ
+
+use crate::error::{Error, Result};
+use crate::PackReader;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoint {
+    pub id: u64,
+    /// Prosecution Order。負の値は進行順に含まれない
+    pub order: i16,
+    /// 緯度(度)
+    pub lat: f64,
+    /// 経度(度)
+    pub lon: f64,
+    /// 高度(HAE, メートル)
+    pub alt: f64,
+}
+
+/// Tag 141のvalueをWaypointの列にする
+///
+/// 128以上のTagはBER-OIDの2Byte(`0x81 0x0D`)で書き込まれ、1ByteのKeyとして読む
+/// `UASDatalinkLS` や `KLVReader` では取り出せないので、valueは呼び出し側で切り出して渡す。
+/// 空のvalueは空の列になる。Packが途中で切れている場合はエラー
+pub fn decode(buf: &[u8]) -> Result<Vec<Waypoint>> {
+    PackReader::from_bytes(buf)
+        .map(|pack| decode_pack(pack?))
+        .collect()
+}
+
+fn decode_pack(pack: &[u8]) -> Result<Waypoint> {
+    let (id, n) = read_ber_oid(pack)?;
+    let rest = &pack[n..];
+    if rest.len() < 2 {
+        return Err(Error::ContentLenght);
+    }
+    let order = i16::from_be_bytes([rest[0], rest[1]]);
+    // Waypoint Infoは読み飛ばす
+    let (_, n) = read_ber_oid(&rest[2..])?;
+    let location = &rest[2 + n..];
+    if location.len() < 11 {
+        return Err(Error::ContentLenght);
+    }
+    Ok(Waypoint {
+        id,
+        order,
+        lat: imapb(-90.0, 90.0, &location[..4]),
+        lon: imapb(-180.0, 180.0, &location[4..8]),
+        alt: imapb(-900.0, 9000.0, &location[8..11]),
+    })
+}
+
+// BER-OIDの(値, byte数)。各byteの下位7bitを上位から並べ、最上位bitが立っていれば続く
+fn read_ber_oid(buf: &[u8]) -> Result<(u64, usize)> {
+    let mut v = 0u64;
+    for (i, x) in buf.iter().enumerate().take(9) {
+        v = v << 7 | (*x & 0x7f) as u64;
+        if x & 0x80 == 0 {
+            return Ok((v, i + 1));
+        }
+    }
+    Err(Error::ContentLenght)
+}
+
+// MISB ST 1201のIMAPBで範囲a..bに写した値を戻す
+fn imapb(a: f64, b: f64, buf: &[u8]) -> f64 {
+    let b_pow = (b - a).log2().ceil() as i32;
+    let d_pow = 8 * buf.len() as i32 - 1;
+    let s_f = 2f64.powi(d_pow - b_pow);
+    let s_r = 2f64.powi(b_pow - d_pow);
+    let z_offset = if a < 0.0 && b > 0.0 {
+        s_f * a - (s_f * a).floor()
+    } else {
+        0.0
+    };
+    let y = buf.iter().fold(0u64, |acc, x| acc << 8 | *x as u64);
+    s_r * (y as f64 - z_offset) + a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, Waypoint};
+    use crate::error::Error;
+
+    #[test]
+    fn test_decode() {
+        let buf = [
+            // id 1, order 1, info 0, 38.5, -77.25, 100.0
+            0x0f, 0x01, 0x00, 0x01, 0x00, 0x40, 0x40, 0x00, 0x00, 0x19, 0xb0, 0x00, 0x00, 0x07,
+            0xd0, 0x00, //
+            // id 200 (2Byte), order -1, info 3, -10.0, 140.0, -900.0
+            0x10, 0x81, 0x48, 0xff, 0xff, 0x03, 0x28, 0x00, 0x00, 0x00, 0x50, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let waypoints = decode(&buf).unwrap();
+        assert_eq!(
+            waypoints,
+            vec![
+                Waypoint {
+                    id: 1,
+                    order: 1,
+                    lat: 38.5,
+                    lon: -77.25,
+                    alt: 100.0,
+                },
+                Waypoint {
+                    id: 200,
+                    order: -1,
+                    lat: -10.0,
+                    lon: 140.0,
+                    alt: -900.0,
+                },
+            ]
+        );
+
+        assert!(decode(&[]).unwrap().is_empty());
+        // Packが途中で切れている
+        assert!(matches!(decode(&buf[..20]), Err(Error::ContentLenght)));
+    }
+}
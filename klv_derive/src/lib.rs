@@ -0,0 +1,285 @@
+//! derive macro for KLV local set struct
+//!
+//! `#[serde(rename = "N")]` の付け忘れや重複は `to_bytes` の実行時に `Error::Key` になる。
+//! `#[derive(Klv)]` はこれをコンパイル時に検出する。
+//!
+//! derive macroは元の定義に属性を追加できないため、renameの生成はattribute macroの `#[klv]` が行う。
+//! fieldの `#[klv(tag = N)]` を `#[serde(rename = "N")]` に書き換えるので、`#[derive(..)]` より前に付ける。
+//!
+//! ```ignore
+//! #[klv]
+//! #[derive(Serialize, Deserialize, Klv)]
+//! #[serde(rename = "TESTDATA00000000")]
+//! struct Test {
+//!     #[klv(tag = 10)]
+//!     x: u8,
+//! }
+//! ```
+//!
+//! `#[derive(KlvTag)]` は `#[repr(u8)]` のenumに、tagとの相互変換を生成する。
+
+use std::collections::BTreeMap;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, LitInt, LitStr};
+
+/// 全てのfieldが数値のtagを持ち、重複していないことを検査する
+///
+/// 検査に通るとfieldの宣言順のtag列を `TAGS` として生成する
+#[proc_macro_derive(Klv)]
+pub fn derive_klv(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(x) => x.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(x) => match &x.fields {
+            Fields::Named(x) => &x.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "Klv supports only struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "Klv supports only struct with named fields",
+            ))
+        }
+    };
+
+    let mut used = BTreeMap::new();
+    let mut tags = vec![];
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let rename = match serde_rename(&field.attrs)? {
+            Some(x) => x,
+            None => {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    format!(
+                        "missing tag, add #[serde(rename = \"N\")] to field `{}`",
+                        ident
+                    ),
+                ))
+            }
+        };
+        let tag = rename
+            .value()
+            .parse::<u8>()
+            .map_err(|_| syn::Error::new_spanned(&rename, "tag must be a number in 0..=255"))?;
+        if let Some(prev) = used.insert(tag, ident.clone()) {
+            return Err(syn::Error::new_spanned(
+                &rename,
+                format!("duplicate tag {}, already used by field `{}`", tag, prev),
+            ));
+        }
+        tags.push(tag);
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// tags of fields in declaration order
+            pub const TAGS: &'static [u8] = &[#(#tags),*];
+        }
+    })
+}
+
+/// fieldの `#[klv(tag = N)]` を `#[serde(rename = "N")]` に書き換える
+///
+/// serdeのderiveに書き換えた属性を読ませるため、`#[derive(..)]` より前に付ける。
+/// `#[klv(tag = N)]` の無いfieldはそのままにする
+#[proc_macro_attribute]
+pub fn klv(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return syn::Error::new_spanned(attr, "klv takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let mut input = parse_macro_input!(item as DeriveInput);
+    match expand_attr(&mut input) {
+        Ok(()) => quote!(#input).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_attr(input: &mut DeriveInput) -> syn::Result<()> {
+    let fields = match &mut input.data {
+        Data::Struct(syn::DataStruct {
+            fields: Fields::Named(x),
+            ..
+        }) => &mut x.named,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "klv supports only struct with named fields",
+            ))
+        }
+    };
+    for field in fields.iter_mut() {
+        let (klv_attrs, attrs) = field
+            .attrs
+            .drain(..)
+            .partition::<Vec<_>, _>(|x| x.path().is_ident("klv"));
+        field.attrs = attrs;
+        let tag = match klv_attrs.as_slice() {
+            [] => continue,
+            [attr] => klv_tag(attr)?,
+            [_, attr, ..] => {
+                return Err(syn::Error::new_spanned(attr, "duplicate klv attribute"));
+            }
+        };
+        if let Some(rename) = serde_rename(&field.attrs)? {
+            return Err(syn::Error::new_spanned(
+                rename,
+                "tag is set by both #[klv(tag = N)] and #[serde(rename)]",
+            ));
+        }
+        let rename = LitStr::new(&tag.to_string(), tag.span());
+        field.attrs.push(parse_quote!(#[serde(rename = #rename)]));
+    }
+    Ok(())
+}
+
+/// `#[klv(tag = N)]` のNを取り出す
+fn klv_tag(attr: &syn::Attribute) -> syn::Result<LitInt> {
+    let mut tag = None;
+    attr.parse_nested_meta(|meta| {
+        if !meta.path.is_ident("tag") {
+            return Err(meta.error("unsupported klv attribute, expected tag = N"));
+        }
+        let lit = meta.value()?.parse::<LitInt>()?;
+        if lit.base10_parse::<u8>().is_err() {
+            return Err(syn::Error::new_spanned(
+                &lit,
+                "tag must be a number in 0..=255",
+            ));
+        }
+        tag = Some(lit);
+        Ok(())
+    })?;
+    tag.ok_or_else(|| syn::Error::new_spanned(attr, "missing tag, expected #[klv(tag = N)]"))
+}
+
+/// `#[repr(u8)]` のenumにtagのbyteとの変換を生成する
+///
+/// `TryFrom<u8>`(未定義のbyteはそのままエラーで返す)と `as_byte`, `name`, `all` を生成する。
+/// discriminantは連続していなくてもよい
+#[proc_macro_derive(KlvTag)]
+pub fn derive_klv_tag(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_tag(&input) {
+        Ok(x) => x.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_tag(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let variants = match &input.data {
+        Data::Enum(x) => &x.variants,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "KlvTag supports only enum with #[repr(u8)]",
+            ))
+        }
+    };
+    if !is_repr_u8(&input.attrs)? {
+        return Err(syn::Error::new_spanned(
+            input,
+            "KlvTag requires #[repr(u8)] on the enum",
+        ));
+    }
+    for variant in variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "KlvTag supports only unit variants",
+            ));
+        }
+    }
+
+    let name = &input.ident;
+    let idents = variants.iter().map(|x| &x.ident).collect::<Vec<_>>();
+    let names = idents.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    // discriminantの式は評価せず、`as u8` でコンパイラに値を求めさせる
+    Ok(quote! {
+        impl ::core::convert::TryFrom<u8> for #name {
+            type Error = u8;
+
+            fn try_from(v: u8) -> ::core::result::Result<Self, u8> {
+                #(
+                    if v == #name::#idents as u8 {
+                        return ::core::result::Result::Ok(#name::#idents);
+                    }
+                )*
+                ::core::result::Result::Err(v)
+            }
+        }
+
+        impl #name {
+            /// tag byte
+            pub const fn as_byte(&self) -> u8 {
+                match self {
+                    #(#name::#idents => #name::#idents as u8,)*
+                }
+            }
+
+            /// variant name
+            pub const fn name(&self) -> &'static str {
+                match self {
+                    #(#name::#idents => #names,)*
+                }
+            }
+
+            /// all variants in declaration order
+            pub const fn all() -> &'static [Self] {
+                &[#(#name::#idents),*]
+            }
+        }
+    })
+}
+
+/// `#[repr(u8)]` が付いているか
+fn is_repr_u8(attrs: &[syn::Attribute]) -> syn::Result<bool> {
+    let mut found = false;
+    for attr in attrs.iter().filter(|x| x.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("u8") {
+                found = true;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(found)
+}
+
+/// `#[serde(rename = "...")]` の値を取り出す
+fn serde_rename(attrs: &[syn::Attribute]) -> syn::Result<Option<LitStr>> {
+    let mut rename = None;
+    for attr in attrs.iter().filter(|x| x.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") && meta.input.peek(syn::Token![=]) {
+                rename = Some(meta.value()?.parse::<LitStr>()?);
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                let content;
+                syn::parenthesized!(content in meta.input);
+                content.parse::<proc_macro2::TokenStream>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(rename)
+}
@@ -0,0 +1,77 @@
+use klv_derive::{klv, Klv};
+use serde::{Deserialize, Serialize};
+
+#[test]
+fn test_derive_tags() {
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Klv)]
+    #[serde(rename = "TESTDATA00000000")]
+    struct Test<'a> {
+        #[serde(rename = "10")]
+        u8: u8,
+        #[serde(rename = "2", skip_serializing_if = "Option::is_none")]
+        some: Option<u16>,
+        #[serde(rename = "255")]
+        str: &'a str,
+    }
+    assert_eq!(Test::TAGS, &[10, 2, 255]);
+}
+
+#[test]
+fn test_klv_attribute() {
+    #[klv]
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Klv)]
+    #[serde(rename = "TESTDATA00000000")]
+    struct Test<'a> {
+        #[klv(tag = 10)]
+        u8: u8,
+        #[klv(tag = 2)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        some: Option<u16>,
+        #[klv(tag = 255)]
+        str: &'a str,
+    }
+    assert_eq!(Test::TAGS, &[10, 2, 255]);
+
+    let t = Test {
+        u8: 1,
+        some: Some(0x1234),
+        str: "abc",
+    };
+    let s = klv::to_bytes(&t).unwrap();
+    assert_eq!(
+        &s[16..],
+        &[12, 10, 1, 1, 2, 2, 0x12, 0x34, 255, 3, b'a', b'b', b'c']
+    );
+    assert_eq!(klv::from_bytes::<Test>(&s).unwrap(), t);
+}
+
+#[test]
+fn test_derive_ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
+
+#[test]
+fn test_derive_klv_tag() {
+    use klv_derive::KlvTag;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, KlvTag)]
+    #[repr(u8)]
+    enum Tag {
+        Checksum = 1,
+        Timestamp,
+        MissionId,
+        LsVersion = 65,
+        Waypoints = 141,
+    }
+    assert_eq!(Tag::all().len(), 5);
+    for tag in Tag::all() {
+        assert_eq!(Tag::try_from(tag.as_byte()), Ok(*tag));
+    }
+    assert_eq!(Tag::Timestamp.as_byte(), 2);
+    assert_eq!(Tag::try_from(141), Ok(Tag::Waypoints));
+    assert_eq!(Tag::LsVersion.name(), "LsVersion");
+    // 未定義のbyte
+    assert_eq!(Tag::try_from(4), Err(4));
+    assert_eq!(Tag::try_from(0), Err(0));
+}
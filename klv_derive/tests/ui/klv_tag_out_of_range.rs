@@ -0,0 +1,14 @@
+use klv_derive::{klv, Klv};
+use serde::Serialize;
+
+#[klv]
+#[derive(Serialize, Klv)]
+#[serde(rename = "TESTDATA00000000")]
+struct Test {
+    #[klv(tag = 10)]
+    u8: u8,
+    #[klv(tag = 256)]
+    u16: u16,
+}
+
+fn main() {}
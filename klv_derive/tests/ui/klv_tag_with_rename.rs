@@ -0,0 +1,13 @@
+use klv_derive::{klv, Klv};
+use serde::Serialize;
+
+#[klv]
+#[derive(Serialize, Klv)]
+#[serde(rename = "TESTDATA00000000")]
+struct Test {
+    #[klv(tag = 10)]
+    #[serde(rename = "11")]
+    u8: u8,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use klv_derive::Klv;
+use serde::Serialize;
+
+#[derive(Serialize, Klv)]
+#[serde(rename = "TESTDATA00000000")]
+struct Test {
+    #[serde(rename = "10")]
+    u8: u8,
+    u16: u16,
+}
+
+fn main() {}
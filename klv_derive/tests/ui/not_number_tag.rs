@@ -0,0 +1,11 @@
+use klv_derive::Klv;
+use serde::Serialize;
+
+#[derive(Serialize, Klv)]
+#[serde(rename = "TESTDATA00000000")]
+struct Test {
+    #[serde(rename = "-1")]
+    u8: u8,
+}
+
+fn main() {}
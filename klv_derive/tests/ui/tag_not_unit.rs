@@ -0,0 +1,10 @@
+use klv_derive::KlvTag;
+
+#[derive(KlvTag)]
+#[repr(u8)]
+enum Tag {
+    Checksum = 1,
+    Timestamp(u64) = 2,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use klv_derive::KlvTag;
+
+#[derive(KlvTag)]
+enum Tag {
+    Checksum = 1,
+    Timestamp = 2,
+}
+
+fn main() {}
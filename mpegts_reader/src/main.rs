@@ -3,15 +3,15 @@ extern crate mpeg2ts_reader;
 extern crate hex_slice;
 
 use hex_slice::AsHex;
-use klv::from_bytes;
 use klv::uasdls::UASDatalinkLS;
-use log::debug;
+use klv::{bcc16, checksum_range, from_bytes, UL_PREFIX};
+use log::{debug, info};
 
 use mpeg2ts_reader::demultiplex;
 use mpeg2ts_reader::packet;
 use mpeg2ts_reader::packet::Pid;
 use mpeg2ts_reader::pes;
-use mpeg2ts_reader::pes::PesHeader;
+use mpeg2ts_reader::pes::{ElementaryStreamConsumer, PesHeader};
 
 use mpeg2ts_reader::pes::Timestamp;
 use mpeg2ts_reader::psi;
@@ -24,6 +24,10 @@ use std::time::Duration;
 
 use structopt::StructOpt;
 
+mod stats;
+
+use stats::Stats;
+
 // This macro invocation creates an enum called DumpFilterSwitch, encapsulating all possible ways
 // that this application may handle transport stream packets.  Each enum variant is just a wrapper
 // around an implementation of the PacketFilter trait
@@ -82,32 +86,103 @@ impl DumpDemuxContext {
     }
 }
 
+/// MISB ST 1402のKLVの同期方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KlvSyncKind {
+    /// PTSを持ち映像と同期する
+    Synchronous,
+    /// PTSを持たない
+    Asynchronous,
+}
+
+/// PES headerにPTSがあれば同期、無ければ非同期とする
+pub fn klv_sync_kind(header: &PesHeader) -> KlvSyncKind {
+    match header.contents() {
+        pes::PesContents::Parsed(Some(parsed)) => match parsed.pts_dts() {
+            Ok(pes::PtsDts::PtsOnly(Ok(_))) | Ok(pes::PtsDts::Both { pts: Ok(_), .. }) => {
+                KlvSyncKind::Synchronous
+            }
+            _ => KlvSyncKind::Asynchronous,
+        },
+        _ => KlvSyncKind::Asynchronous,
+    }
+}
+
+/// PESのpayloadがUniversal Keyから始まっているか
+///
+/// mpegtsmuxのalignment=7ではセットの先頭がPESの先頭に揃うので、1PESに1セットとして扱える
+pub fn is_klv_aligned(pes_payload: &[u8]) -> bool {
+    pes_payload.len() >= 16 && pes_payload.starts_with(&UL_PREFIX)
+}
+
 // Implement the ElementaryStreamConsumer to just dump and PTS/DTS timestamps to stdout
 pub struct PtsDumpElementaryStreamConsumer {
     pid: packet::Pid,
     format: StreamType,
     len: Option<usize>,
     buf: Vec<u8>,
+    stats: Stats,
+    sync_kind: KlvSyncKind,
+    // continuity errorの後、次のbegin_packetまでのデータを捨てる
+    discarding: bool,
 }
 impl PtsDumpElementaryStreamConsumer {
+    fn new(pid: packet::Pid, format: StreamType) -> Self {
+        PtsDumpElementaryStreamConsumer {
+            pid,
+            format,
+            len: None,
+            buf: vec![],
+            stats: Stats::default(),
+            sync_kind: KlvSyncKind::Asynchronous,
+            discarding: false,
+        }
+    }
+
     fn construct(
         _pmt_sect: &psi::pmt::PmtSection,
         stream_info: &psi::pmt::StreamInfo,
     ) -> DumpFilterSwitch {
-        let filter = pes::PesPacketFilter::new(PtsDumpElementaryStreamConsumer {
-            pid: stream_info.elementary_pid(),
-            format: stream_info.stream_type(),
-            len: None,
-            buf: vec![],
-        });
+        let filter = pes::PesPacketFilter::new(PtsDumpElementaryStreamConsumer::new(
+            stream_info.elementary_pid(),
+            stream_info.stream_type(),
+        ));
         DumpFilterSwitch::Pes(filter)
     }
+
+    /// demuxを通さずにTS packetを渡す(--raw)
+    ///
+    /// PesPacketFilterと同様にpayload_unit_start_indicatorでPESを区切るので、
+    /// 複数のpacketに分かれたKLVセットやParsed(None)のヘッダも通常の経路と同じく組み立てる
+    fn push_packet(&mut self, ctx: &mut DumpDemuxContext, pk: &packet::Packet<'_>) {
+        let Some(payload) = pk.payload() else {
+            return;
+        };
+        if pk.payload_unit_start_indicator() {
+            self.finish_packet(ctx);
+            match PesHeader::from_bytes(payload) {
+                Some(header) => self.begin_packet(ctx, header),
+                None => debug!("{:?}: invalid PES header", self.pid),
+            }
+        } else if self.len.is_some() {
+            self.continue_packet(ctx, payload);
+        }
+    }
+
+    // 組み立て中のPESがあれば終える
+    fn finish_packet(&mut self, ctx: &mut DumpDemuxContext) {
+        if self.len.take().is_some() {
+            self.end_packet(ctx);
+        }
+    }
 }
 impl pes::ElementaryStreamConsumer<DumpDemuxContext> for PtsDumpElementaryStreamConsumer {
     fn start_stream(&mut self, _ctx: &mut DumpDemuxContext) {
         println!("start stream: {:?}", self.format);
     }
     fn begin_packet(&mut self, _ctx: &mut DumpDemuxContext, header: pes::PesHeader) {
+        self.sync_kind = klv_sync_kind(&header);
+        self.discarding = false;
         match header.contents() {
             pes::PesContents::Parsed(Some(parsed)) => {
                 match parsed.pts_dts() {
@@ -129,9 +204,15 @@ impl pes::ElementaryStreamConsumer<DumpDemuxContext> for PtsDumpElementaryStream
                 self.len = Some(payload.len());
                 self.buf.extend_from_slice(payload);
             }
-            pes::PesContents::Parsed(None) => println!("parsed"),
+            // ヘッダから先頭のpayloadを取り出せなくても、続くデータはcontinue_packetで届くので
+            // bufはそのままにして組み立てを続ける
+            pes::PesContents::Parsed(None) => {
+                debug!("{:?}: PES header has no payload", self.pid);
+                self.len = Some(0);
+            }
             pes::PesContents::Payload(payload) => {
                 self.len = Some(payload.len());
+                self.buf.extend_from_slice(payload);
                 println!(
                     "{:?}:                               {:02x}",
                     self.pid,
@@ -141,15 +222,46 @@ impl pes::ElementaryStreamConsumer<DumpDemuxContext> for PtsDumpElementaryStream
         }
     }
     fn continue_packet(&mut self, _ctx: &mut DumpDemuxContext, data: &[u8]) {
+        if self.discarding {
+            return;
+        }
         self.buf.extend_from_slice(data);
         self.len = self.len.map(|l| l + data.len());
     }
     fn end_packet(&mut self, _ctx: &mut DumpDemuxContext) {
+        if !self.buf.is_empty() && !is_klv_aligned(&self.buf) {
+            debug!("{:?}: PES payload is not aligned to a KLV set", self.pid);
+        }
         if let Ok(d) = from_bytes::<UASDatalinkLS>(&self.buf) {
-            println!("x {:?}", d);
+            self.stats.sets_total += 1;
+            let checksum = checksum_range(&self.buf, 1).map(|x| bcc16(&self.buf[x]));
+            if checksum != Some(d.checksum) {
+                self.stats.checksum_failures_total += 1;
+            }
+            println!("x {:?} {:?}", self.sync_kind, d);
         }
+        // 次のPESの組み立てはbegin_packetからになるのでここで捨てる
+        self.buf.clear();
+    }
+    fn continuity_error(&mut self, _ctx: &mut DumpDemuxContext) {
+        // packetが欠けたPESを組み立てても壊れたセットになるので、次のPESまで読み飛ばす
+        debug!(
+            "{:?}: continuity error, discard {} bytes",
+            self.pid,
+            self.buf.len()
+        );
+        self.stats.continuity_errors_total += 1;
+        self.buf.clear();
+        self.len = None;
+        self.discarding = true;
+    }
+}
+
+// 読み終わってfilterが破棄される際に累計を出力する
+impl Drop for PtsDumpElementaryStreamConsumer {
+    fn drop(&mut self) {
+        info!("{:?}: metrics\n{}", self.pid, self.stats.render_metrics());
     }
-    fn continuity_error(&mut self, _ctx: &mut DumpDemuxContext) {}
 }
 
 #[derive(Debug, StructOpt)]
@@ -177,6 +289,10 @@ fn main() {
     // create the demultiplexer, which will use the ctx to create a filter for pid 0 (PAT)
     let mut demux = demultiplex::Demultiplex::new(&mut ctx);
 
+    // --rawではKLVのPIDだけをdemuxを通さずに読む
+    let mut raw_klv =
+        PtsDumpElementaryStreamConsumer::new(Pid::new(0x1f1), StreamType::H2220PesPrivateData);
+
     // consume the input file,
     let mut buf = [0u8; 188 * 1024];
     loop {
@@ -190,26 +306,8 @@ fn main() {
                         .map(packet::Packet::try_new);
 
                     for pk in itr.into_iter().flatten() {
-                        if pk.pid() == Pid::PAT {
-                            // println!("pat {:?}", pk.payload());
-                        } else if pk.pid() == Pid::new(0x1f1) {
-                            let payload = pk.payload().unwrap();
-                            match PesHeader::from_bytes(payload).unwrap().contents() {
-                                pes::PesContents::Parsed(Some(ppc)) => {
-                                    let buf = ppc.payload();
-                                    if let Ok(d) = from_bytes::<UASDatalinkLS>(buf) {
-                                        println!("x {:?}", d);
-                                    }
-                                }
-                                pes::PesContents::Parsed(None) => {
-                                    println!("pat {:?}, None", pk.pid());
-                                }
-                                pes::PesContents::Payload(buf) => {
-                                    println!("pat {:?}, {:02x?}", pk.pid(), buf);
-                                }
-                            }
-                        } else {
-                            // println!("pid {:?} {:?}", pk.pid(), pk.adaptation_field());
+                        if pk.pid() == Pid::new(0x1f1) {
+                            raw_klv.push_packet(&mut ctx, &pk);
                         }
                     }
                 } else {
@@ -218,4 +316,173 @@ fn main() {
             }
         }
     }
+    raw_klv.finish_packet(&mut ctx);
+}
+
+#[cfg(test)]
+mod tests {
+    use klv::{from_bytes, uasdls::UASDatalinkLS};
+    use mpeg2ts_reader::pes::{self, ElementaryStreamConsumer};
+    use mpeg2ts_reader::{packet, StreamType};
+
+    use crate::{
+        is_klv_aligned, klv_sync_kind, DumpDemuxContext, KlvSyncKind,
+        PtsDumpElementaryStreamConsumer,
+    };
+
+    #[rustfmt::skip]
+    const KLV_SET: &[u8] = &[
+        0x06, 0x0e, 0x2b, 0x34, 0x02, 0x0b, 0x01, 0x01, 0x0e, 0x01, 0x03, 0x01, 0x01, 0x00, 0x00, 0x00,
+        29,
+        2, 8, 0, 0x4, 0x6c, 0x8e, 0x20, 0x03, 0x83, 0x85,
+        5, 2, 0x3d, 0x3b,
+        6, 2, 0x15, 0x80,
+        7, 2, 0x01, 0x52,
+        65, 1, 1,
+        1, 2, 0x1c, 0x5f,
+    ];
+
+    fn consumer() -> PtsDumpElementaryStreamConsumer {
+        PtsDumpElementaryStreamConsumer::new(
+            packet::Pid::new(0x1f1),
+            StreamType::H2220PesPrivateData,
+        )
+    }
+
+    // PID 0x1f1のTS packet。payloadが184Byteに満たない分はadaptation fieldで埋める
+    fn ts_packet(start: bool, cc: u8, payload: &[u8]) -> [u8; packet::Packet::SIZE] {
+        let mut buf = [0xff; packet::Packet::SIZE];
+        buf[0] = 0x47;
+        buf[1] = if start { 0x41 } else { 0x01 };
+        buf[2] = 0xf1;
+        buf[3] = 0x30 | cc;
+        let af_len = packet::Packet::SIZE - 5 - payload.len();
+        buf[4] = af_len as u8;
+        if af_len > 0 {
+            buf[5] = 0x00;
+        }
+        buf[5 + af_len..].copy_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn test_parsed_none_then_continue() {
+        let mut ctx = DumpDemuxContext::new();
+        let mut c = consumer();
+        // private_stream_1でPES headerが途中で切れているとParsed(None)になる
+        let header = [0x00, 0x00, 0x01, 0xbd, 0x00, 0x00, 0x80];
+        let header = pes::PesHeader::from_bytes(&header).unwrap();
+        assert!(matches!(header.contents(), pes::PesContents::Parsed(None)));
+
+        c.begin_packet(&mut ctx, header);
+        let (first, second) = KLV_SET.split_at(10);
+        c.continue_packet(&mut ctx, first);
+        c.continue_packet(&mut ctx, second);
+        assert_eq!(c.buf, KLV_SET);
+        let x = from_bytes::<UASDatalinkLS>(&c.buf).unwrap();
+        assert_eq!(x.platform_heading_angle, 15675);
+
+        c.end_packet(&mut ctx);
+        assert!(c.buf.is_empty());
+        assert_eq!(c.stats.sets_total, 1);
+        // KLV_SETのchecksumは別のセットのもの
+        assert_eq!(c.stats.checksum_failures_total, 1);
+    }
+
+    #[test]
+    fn test_klv_sync_kind() {
+        // PTSのみ
+        #[rustfmt::skip]
+        let header = [
+            0x00, 0x00, 0x01, 0xbd, 0x00, 0x00,
+            0x80, 0x80, 0x05,
+            0x21, 0x00, 0x01, 0x00, 0x01,
+            0x06, 0x0e,
+        ];
+        let header = pes::PesHeader::from_bytes(&header).unwrap();
+        assert_eq!(klv_sync_kind(&header), KlvSyncKind::Synchronous);
+
+        // PTSなし
+        let header = [
+            0x00, 0x00, 0x01, 0xbd, 0x00, 0x00, 0x80, 0x00, 0x00, 0x06, 0x0e,
+        ];
+        let header = pes::PesHeader::from_bytes(&header).unwrap();
+        assert!(matches!(
+            header.contents(),
+            pes::PesContents::Parsed(Some(_))
+        ));
+        assert_eq!(klv_sync_kind(&header), KlvSyncKind::Asynchronous);
+    }
+
+    #[test]
+    fn test_is_klv_aligned() {
+        assert!(is_klv_aligned(KLV_SET));
+        // セットの途中から始まる
+        assert!(!is_klv_aligned(&KLV_SET[1..]));
+        assert!(!is_klv_aligned(&KLV_SET[17..]));
+        // Keyの途中で切れている
+        assert!(!is_klv_aligned(&KLV_SET[..8]));
+        assert!(!is_klv_aligned(&[]));
+    }
+
+    #[test]
+    fn test_continuity_error() {
+        let mut ctx = DumpDemuxContext::new();
+        let mut c = consumer();
+        let header = [0x00, 0x00, 0x01, 0xbd, 0x00, 0x00, 0x80];
+
+        let (first, second) = KLV_SET.split_at(10);
+        c.begin_packet(&mut ctx, pes::PesHeader::from_bytes(&header).unwrap());
+        c.continue_packet(&mut ctx, first);
+        c.continuity_error(&mut ctx);
+        // 欠けた後に届いたデータは捨てる
+        c.continue_packet(&mut ctx, second);
+        assert!(c.buf.is_empty());
+        c.end_packet(&mut ctx);
+        assert_eq!(c.stats.continuity_errors_total, 1);
+        assert_eq!(c.stats.sets_total, 0);
+
+        // 次のPESは読める
+        c.begin_packet(&mut ctx, pes::PesHeader::from_bytes(&header).unwrap());
+        c.continue_packet(&mut ctx, KLV_SET);
+        assert_eq!(c.buf, KLV_SET);
+        c.end_packet(&mut ctx);
+        assert_eq!(c.stats.sets_total, 1);
+    }
+
+    #[test]
+    fn test_push_packet() {
+        let mut ctx = DumpDemuxContext::new();
+        let mut c = consumer();
+        let (first, second) = KLV_SET.split_at(10);
+        let mut pes = vec![0x00, 0x00, 0x01, 0xbd, 0x00, 0x00, 0x80, 0x00, 0x00];
+        pes.extend_from_slice(first);
+        // 1つのKLVセットが2つのpacketに分かれている
+        let packets = [
+            ts_packet(true, 0, &pes),
+            ts_packet(false, 1, second),
+            ts_packet(true, 2, &pes),
+        ];
+        for buf in &packets {
+            let pk = packet::Packet::new(buf);
+            c.push_packet(&mut ctx, &pk);
+        }
+        // 2つ目のPESの開始で1つ目を組み立て終える
+        assert_eq!(c.stats.sets_total, 1);
+        assert_eq!(c.buf, first);
+        c.finish_packet(&mut ctx);
+        assert_eq!(c.stats.sets_total, 1);
+        assert!(c.buf.is_empty());
+
+        // ヘッダがParsed(None)でも続くpacketから組み立てる
+        let mut c = consumer();
+        let header = [0x00, 0x00, 0x01, 0xbd, 0x00, 0x00, 0x80];
+        c.push_packet(&mut ctx, &packet::Packet::new(&ts_packet(true, 0, &header)));
+        c.push_packet(
+            &mut ctx,
+            &packet::Packet::new(&ts_packet(false, 1, KLV_SET)),
+        );
+        c.finish_packet(&mut ctx);
+        assert_eq!(c.stats.sets_total, 1);
+    }
 }
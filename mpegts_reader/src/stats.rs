@@ -0,0 +1,71 @@
+//! 読み込み中の統計
+
+use std::fmt::Write;
+
+/// KLVセットとTSの読み込み結果の累計
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// デコードできたKLVセットの数
+    pub sets_total: u64,
+    /// checksumが一致しなかったKLVセットの数
+    pub checksum_failures_total: u64,
+    /// TSのcontinuity counterの不連続を検出した数
+    pub continuity_errors_total: u64,
+}
+
+impl Stats {
+    /// Prometheusのtext formatで出力する
+    pub fn render_metrics(&self) -> String {
+        let metrics = [
+            (
+                "klv_sets_total",
+                "Number of decoded KLV sets.",
+                self.sets_total,
+            ),
+            (
+                "klv_checksum_failures_total",
+                "Number of KLV sets with a checksum mismatch.",
+                self.checksum_failures_total,
+            ),
+            (
+                "ts_continuity_errors_total",
+                "Number of TS continuity errors.",
+                self.continuity_errors_total,
+            ),
+        ];
+        let mut s = String::new();
+        for (name, help, value) in metrics {
+            writeln!(s, "# HELP {} {}", name, help).unwrap();
+            writeln!(s, "# TYPE {} counter", name).unwrap();
+            writeln!(s, "{} {}", name, value).unwrap();
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::Stats;
+
+    #[test]
+    fn test_render_metrics() {
+        let stats = Stats {
+            sets_total: 42,
+            checksum_failures_total: 3,
+            continuity_errors_total: 1,
+        };
+        assert_eq!(
+            stats.render_metrics(),
+            "# HELP klv_sets_total Number of decoded KLV sets.
+# TYPE klv_sets_total counter
+klv_sets_total 42
+# HELP klv_checksum_failures_total Number of KLV sets with a checksum mismatch.
+# TYPE klv_checksum_failures_total counter
+klv_checksum_failures_total 3
+# HELP ts_continuity_errors_total Number of TS continuity errors.
+# TYPE ts_continuity_errors_total counter
+ts_continuity_errors_total 1
+"
+        );
+    }
+}